@@ -0,0 +1,376 @@
+use nalgebra::Vector3;
+
+use crate::{
+    continuity::SharedEdge,
+    point::{Points2DArr, PosIn2DArr},
+    triangle::Triangle,
+};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TriangulationMode {
+    /// Regular grid with the same number of samples in each direction
+    Uniform,
+    /// Cells are subdivided until they are flat enough in 3D
+    AdaptiveFlatness,
+    /// Cells are subdivided until their projection onto the screen is flat enough
+    AdaptiveScreenSpace,
+}
+
+impl TriangulationMode {
+    pub const ALL: [TriangulationMode; 3] = [
+        TriangulationMode::Uniform,
+        TriangulationMode::AdaptiveFlatness,
+        TriangulationMode::AdaptiveScreenSpace,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TriangulationMode::Uniform => "Uniform",
+            TriangulationMode::AdaptiveFlatness => "Adaptive (flatness)",
+            TriangulationMode::AdaptiveScreenSpace => "Adaptive (screen-space)",
+        }
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        *self != TriangulationMode::Uniform
+    }
+}
+
+/// Square cell of the sampling grid, `size` is a power of two
+#[derive(Clone, Copy)]
+struct Cell {
+    row: usize,
+    col: usize,
+    size: usize,
+}
+
+/// Quadtree subdivision of the parameter space over grid of `2^MAX_LEVEL + 1` samples.
+/// Every leaf is triangulated using all leaf corners lying on its boundary,
+/// so there are no T-junctions between neighbouring cells of different sizes.
+pub struct AdaptiveTriangulation;
+
+/// Leaves of the quadtree of a single patch and grid samples used as vertices
+pub struct Refinement {
+    leaves: Vec<Cell>,
+    is_vertex: Vec<bool>,
+    rows: usize,
+    cols: usize,
+}
+
+impl AdaptiveTriangulation {
+    pub const MAX_LEVEL: u32 = 6;
+    // Cells are always split a few times, so features between samples of big cells aren't missed
+    const MIN_LEVEL: u32 = 2;
+
+    pub fn grid_size() -> usize {
+        (1 << Self::MAX_LEVEL) + 1
+    }
+
+    pub fn refine(points: &Points2DArr, mode: TriangulationMode, tolerance: f32) -> Refinement {
+        let leaves = Self::subdivide(points, mode, tolerance);
+
+        let mut is_vertex = vec![false; points.rows() * points.cols()];
+        for leaf in leaves.iter() {
+            for (row, col) in [
+                (leaf.row, leaf.col),
+                (leaf.row + leaf.size, leaf.col),
+                (leaf.row, leaf.col + leaf.size),
+                (leaf.row + leaf.size, leaf.col + leaf.size),
+            ] {
+                is_vertex[points.get_index(row, col)] = true;
+            }
+        }
+        Refinement {
+            leaves,
+            is_vertex,
+            rows: points.rows(),
+            cols: points.cols(),
+        }
+    }
+
+    fn subdivide(points: &Points2DArr, mode: TriangulationMode, tolerance: f32) -> Vec<Cell> {
+        let mut leaves = vec![];
+        let mut stack = vec![(
+            Cell {
+                row: 0,
+                col: 0,
+                size: points.rows() - 1,
+            },
+            0,
+        )];
+        while let Some((cell, level)) = stack.pop() {
+            let should_split = cell.size > 1
+                && (level < Self::MIN_LEVEL || Self::cell_error(points, &cell, mode) > tolerance);
+            if !should_split {
+                leaves.push(cell);
+                continue;
+            }
+            let half = cell.size / 2;
+            for (row, col) in [
+                (cell.row, cell.col),
+                (cell.row + half, cell.col),
+                (cell.row, cell.col + half),
+                (cell.row + half, cell.col + half),
+            ] {
+                stack.push((
+                    Cell {
+                        row,
+                        col,
+                        size: half,
+                    },
+                    level + 1,
+                ));
+            }
+        }
+        leaves
+    }
+
+    /// Largest distance between the surface and bilinear interpolation of cell corners,
+    /// measured at cell center and midpoints of its edges
+    fn cell_error(points: &Points2DArr, cell: &Cell, mode: TriangulationMode) -> f32 {
+        let position = |row: usize, col: usize| -> Vector3<f32> {
+            let point = points.at(row, col);
+            match mode {
+                TriangulationMode::AdaptiveScreenSpace => {
+                    let p = point.after_rotation().p();
+                    Vector3::new(p.x, p.y, 0.0)
+                }
+                _ => point.before_rotation().p(),
+            }
+        };
+        let (row, col, size) = (cell.row, cell.col, cell.size);
+        let half = size / 2;
+        let corners = [
+            position(row, col),
+            position(row, col + size),
+            position(row + size, col),
+            position(row + size, col + size),
+        ];
+        let bilinear = |a: f32, b: f32| {
+            corners[0] * (1.0 - a) * (1.0 - b)
+                + corners[1] * (1.0 - a) * b
+                + corners[2] * a * (1.0 - b)
+                + corners[3] * a * b
+        };
+        [
+            (half, half, 0.5, 0.5),
+            (0, half, 0.0, 0.5),
+            (size, half, 1.0, 0.5),
+            (half, 0, 0.5, 0.0),
+            (half, size, 0.5, 1.0),
+        ]
+        .iter()
+        .map(|&(d_row, d_col, a, b)| (position(row + d_row, col + d_col) - bilinear(a, b)).norm())
+        .fold(0.0, f32::max)
+    }
+
+    /// All grid samples on the cell boundary, in order around the cell
+    fn cell_perimeter(cell: &Cell) -> Vec<PosIn2DArr> {
+        let (row, col, size) = (cell.row, cell.col, cell.size);
+        let mut perimeter = Vec::with_capacity(4 * size);
+        perimeter.extend((0..size).map(|i| PosIn2DArr { row, col: col + i }));
+        perimeter.extend((0..size).map(|i| PosIn2DArr {
+            row: row + i,
+            col: col + size,
+        }));
+        perimeter.extend((0..size).map(|i| PosIn2DArr {
+            row: row + size,
+            col: col + size - i,
+        }));
+        perimeter.extend((0..size).map(|i| PosIn2DArr {
+            row: row + size - i,
+            col,
+        }));
+        perimeter
+    }
+}
+
+impl Refinement {
+    /// Makes both patches use the same vertices along their common boundary, so refinement
+    /// on one side of the boundary doesn't leave T-junctions and cracks on the other side
+    pub fn share_edge(a: &mut Refinement, b: &mut Refinement, shared_edge: &SharedEdge) {
+        let count = shared_edge.edge_a.samples_count(a.rows, a.cols);
+        if shared_edge.edge_b.samples_count(b.rows, b.cols) != count {
+            return;
+        }
+        for i in 0..count {
+            let j = match shared_edge.reversed {
+                true => count - 1 - i,
+                false => i,
+            };
+            let id_a = a.index(shared_edge.edge_a.grid_position(i, a.rows, a.cols));
+            let id_b = b.index(shared_edge.edge_b.grid_position(j, b.rows, b.cols));
+            let is_vertex = a.is_vertex[id_a] || b.is_vertex[id_b];
+            a.is_vertex[id_a] = is_vertex;
+            b.is_vertex[id_b] = is_vertex;
+        }
+    }
+
+    fn index(&self, pos: PosIn2DArr) -> usize {
+        pos.row * self.cols + pos.col
+    }
+
+    pub fn triangles(&self) -> Vec<Triangle> {
+        let mut triangles = vec![];
+        for leaf in self.leaves.iter() {
+            let boundary: Vec<PosIn2DArr> = AdaptiveTriangulation::cell_perimeter(leaf)
+                .into_iter()
+                .filter(|pos| self.is_vertex[self.index(*pos)])
+                .collect();
+            // Perimeter goes clockwise in (u, v), so triangles take it in reverse order
+            // to keep the same winding as uniform triangulation
+            if boundary.len() == 4 {
+                triangles.push(Triangle::new([boundary[0], boundary[2], boundary[1]]));
+                triangles.push(Triangle::new([boundary[0], boundary[3], boundary[2]]));
+            } else {
+                // Leaf has neighbours split into smaller cells (or shares boundary with such
+                // a patch), so its size is at least 2 and its center is a sample of the grid
+                let center = PosIn2DArr {
+                    row: leaf.row + leaf.size / 2,
+                    col: leaf.col + leaf.size / 2,
+                };
+                for i in 0..boundary.len() {
+                    let next = (i + 1) % boundary.len();
+                    triangles.push(Triangle::new([center, boundary[next], boundary[i]]));
+                }
+            }
+        }
+        triangles
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+    use crate::{continuity::PatchEdge, control_points::ControlPoints, point::Point};
+
+    const TOLERANCE: f32 = 0.5;
+
+    fn evaluate(control_points: &ControlPoints) -> Points2DArr {
+        let size = AdaptiveTriangulation::grid_size();
+        let parameter = |i: usize| i as f32 / (size - 1) as f32;
+        let data = (0..size * size)
+            .map(|id| {
+                Point::from_control_points(
+                    parameter(id / size),
+                    parameter(id % size),
+                    control_points,
+                )
+            })
+            .collect();
+        Points2DArr::from_data(data, size, size)
+    }
+
+    /// For each sample along `edge`, whether any triangle uses it
+    fn used_on_edge(triangles: &[Triangle], edge: PatchEdge, reversed: bool) -> Vec<bool> {
+        let size = AdaptiveTriangulation::grid_size();
+        let mut used = vec![false; size * size];
+        for vertex in triangles.iter().flat_map(|triangle| triangle.vertices()) {
+            used[vertex.row * size + vertex.col] = true;
+        }
+        let mut on_edge: Vec<bool> = (0..size)
+            .map(|i| {
+                let pos = edge.grid_position(i, size, size);
+                used[pos.row * size + pos.col]
+            })
+            .collect();
+        if reversed {
+            on_edge.reverse();
+        }
+        on_edge
+    }
+
+    /// Patch strongly bent along `v`, spanning x in [-300, 0]
+    fn bent_patch() -> ControlPoints {
+        let bent_z = [0.0, 0.0, 300.0, 0.0];
+        ControlPoints::new(
+            (0..4)
+                .flat_map(|row| {
+                    (0..4).map(move |col| {
+                        Vector3::new(
+                            -300.0 + 100.0 * col as f32,
+                            150.0 - 100.0 * row as f32,
+                            bent_z[col],
+                        )
+                    })
+                })
+                .collect(),
+            4,
+            4,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn triangles_face_along_normals() {
+        let points = evaluate(&bent_patch());
+        for mode in [
+            TriangulationMode::AdaptiveFlatness,
+            TriangulationMode::AdaptiveScreenSpace,
+        ] {
+            let refinement = AdaptiveTriangulation::refine(&points, mode, TOLERANCE);
+            for triangle in refinement.triangles() {
+                let data = |i: usize| points.at_pos(triangle.vertices()[i]).before_rotation();
+                let (a, b, c) = (data(0), data(1), data(2));
+                let normal = (b.p() - a.p()).cross(&(c.p() - a.p()));
+                assert!(normal.dot(&(a.n() + b.n() + c.n())) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn shared_edge_has_the_same_vertices_on_both_sides() {
+        // Strongly bent patch next to a flat one, so they are refined differently along the boundary
+        let bent = bent_patch();
+        let flat = ControlPoints::new(
+            vec![
+                Vector3::new(0.0, 150.0, 0.0),
+                Vector3::new(300.0, 150.0, 0.0),
+                Vector3::new(0.0, -150.0, 0.0),
+                Vector3::new(300.0, -150.0, 0.0),
+            ],
+            2,
+            2,
+        )
+        .unwrap();
+        let patches = [bent, flat];
+        let shared_edges = SharedEdge::find_all(&patches);
+        assert_eq!(shared_edges.len(), 1);
+        let shared_edge = &shared_edges[0];
+
+        let points: Vec<Points2DArr> = patches.iter().map(evaluate).collect();
+        let mut refinements: Vec<Refinement> = points
+            .iter()
+            .map(|points| {
+                AdaptiveTriangulation::refine(
+                    points,
+                    TriangulationMode::AdaptiveFlatness,
+                    TOLERANCE,
+                )
+            })
+            .collect();
+        let used = |refinements: &[Refinement]| {
+            (
+                used_on_edge(&refinements[0].triangles(), shared_edge.edge_a, false),
+                used_on_edge(
+                    &refinements[1].triangles(),
+                    shared_edge.edge_b,
+                    shared_edge.reversed,
+                ),
+            )
+        };
+
+        let (before_a, before_b) = used(&refinements);
+        assert_ne!(before_a, before_b, "patches should be refined differently");
+        let (head, tail) = refinements.split_at_mut(1);
+        Refinement::share_edge(&mut head[0], &mut tail[0], shared_edge);
+        let (after_a, after_b) = used(&refinements);
+        assert_eq!(after_a, after_b);
+        assert!(after_a
+            .iter()
+            .zip(before_a.iter())
+            .all(|(&after, &before)| after || !before));
+    }
+}
@@ -0,0 +1,361 @@
+use anyhow::{Error, Result};
+
+/// Family of univariate basis functions used along one parameter of a patch
+#[derive(Debug, PartialEq, Clone)]
+pub enum Basis {
+    /// Bernstein polynomials of given degree
+    Bernstein { degree: usize },
+    /// B-spline basis functions defined by knot vector
+    BSpline { degree: usize, knots: Vec<f32> },
+}
+
+/// Values, first and second derivatives of all basis functions at a single parameter
+#[derive(Debug, Clone)]
+pub struct BasisValues {
+    pub values: Vec<f32>,
+    pub derivatives: Vec<f32>,
+    pub second_derivatives: Vec<f32>,
+}
+
+/// Basis values precomputed at evenly spaced parameters `i / (len - 1)` in [0, 1],
+/// so evaluating a grid of points doesn't recompute them for each point
+pub struct BasisTable {
+    samples: Vec<BasisValues>,
+}
+
+impl BasisTable {
+    pub fn new(basis: &Basis, samples_count: usize) -> Self {
+        let samples = (0..samples_count)
+            .map(|i| basis.evaluate(Self::parameter_of(i, samples_count)))
+            .collect();
+        Self { samples }
+    }
+
+    fn parameter_of(i: usize, samples_count: usize) -> f32 {
+        match samples_count > 1 {
+            true => i as f32 / (samples_count - 1) as f32,
+            false => 0.0,
+        }
+    }
+
+    pub fn parameter(&self, i: usize) -> f32 {
+        Self::parameter_of(i, self.samples.len())
+    }
+
+    pub fn at(&self, i: usize) -> &BasisValues {
+        &self.samples[i]
+    }
+}
+
+impl Basis {
+    pub fn bspline(degree: usize, knots: Vec<f32>, count: usize) -> Result<Self> {
+        if degree == 0 || count <= degree {
+            return Err(Error::msg(format!(
+                "Invalid B-spline degree {} for {} control points",
+                degree, count
+            )));
+        }
+        if knots.len() != count + degree + 1 {
+            return Err(Error::msg(format!(
+                "Invalid number of knots (got {}, expected {})",
+                knots.len(),
+                count + degree + 1
+            )));
+        }
+        if knots.windows(2).any(|pair| pair[0] > pair[1]) {
+            return Err(Error::msg("Knots must be non-decreasing"));
+        }
+        if knots[degree] >= knots[count] {
+            return Err(Error::msg("Knot vector defines empty parameter domain"));
+        }
+        Ok(Basis::BSpline { degree, knots })
+    }
+
+    /// Knot vector with `degree + 1` repeated knots at both ends, so the surface
+    /// interpolates corner control points
+    pub fn clamped_uniform(degree: usize, count: usize) -> Result<Self> {
+        let inner_spans = count.saturating_sub(degree).max(1);
+        let knots = (0..(count + degree + 1))
+            .map(|i| i.saturating_sub(degree).min(inner_spans) as f32 / inner_spans as f32)
+            .collect();
+        Self::bspline(degree, knots, count)
+    }
+
+    /// Evenly spaced knots without repetitions. Surface does not pass through
+    /// boundary control points, but every control point has the same local influence.
+    pub fn uniform(degree: usize, count: usize) -> Result<Self> {
+        let knots = (0..(count + degree + 1)).map(|i| i as f32).collect();
+        Self::bspline(degree, knots, count)
+    }
+
+    pub fn degree(&self) -> usize {
+        match self {
+            Basis::Bernstein { degree } | Basis::BSpline { degree, .. } => *degree,
+        }
+    }
+
+    /// Knot vector of the basis. Bernstein polynomials are B-splines with
+    /// `degree + 1` knots at both ends of [0, 1].
+    pub fn knots(&self) -> Vec<f32> {
+        match self {
+            Basis::Bernstein { degree } => (0..(2 * degree + 2))
+                .map(|i| if i <= *degree { 0.0 } else { 1.0 })
+                .collect(),
+            Basis::BSpline { knots, .. } => knots.clone(),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        match self {
+            Basis::Bernstein { degree } => degree + 1,
+            Basis::BSpline { degree, knots } => knots.len() - degree - 1,
+        }
+    }
+
+    /// Evaluates all basis functions at `t` in [0, 1], which is mapped onto
+    /// the parameter domain of the basis
+    pub fn evaluate(&self, t: f32) -> BasisValues {
+        match self {
+            Basis::Bernstein { degree } => Self::evaluate_bernstein(*degree, t),
+            Basis::BSpline { degree, knots } => Self::evaluate_bspline(*degree, knots, t),
+        }
+    }
+
+    fn evaluate_bernstein(degree: usize, t: f32) -> BasisValues {
+        let values = Self::bernstein(degree, t);
+        let mut derivatives = vec![0.0; degree + 1];
+        let mut second_derivatives = vec![0.0; degree + 1];
+        if degree > 0 {
+            // B'(n, i) = n * (B(n - 1, i - 1) - B(n - 1, i))
+            let lower = Self::bernstein(degree - 1, t);
+            for (i, derivative) in derivatives.iter_mut().enumerate() {
+                let left = if i > 0 { lower[i - 1] } else { 0.0 };
+                let right = if i < degree { lower[i] } else { 0.0 };
+                *derivative = degree as f32 * (left - right);
+            }
+        }
+        if degree > 1 {
+            // B''(n, i) = n * (n - 1) * (B(n - 2, i - 2) - 2 * B(n - 2, i - 1) + B(n - 2, i))
+            let lower = Self::bernstein(degree - 2, t);
+            let at = |i: isize| match i >= 0 && i <= degree as isize - 2 {
+                true => lower[i as usize],
+                false => 0.0,
+            };
+            for (i, derivative) in second_derivatives.iter_mut().enumerate() {
+                let i = i as isize;
+                *derivative =
+                    (degree * (degree - 1)) as f32 * (at(i - 2) - 2.0 * at(i - 1) + at(i));
+            }
+        }
+        BasisValues {
+            values,
+            derivatives,
+            second_derivatives,
+        }
+    }
+
+    fn bernstein(degree: usize, t: f32) -> Vec<f32> {
+        (0..(degree + 1))
+            .map(|i| {
+                Self::binomial(degree, i) * t.powi(i as i32) * (1.0 - t).powi((degree - i) as i32)
+            })
+            .collect()
+    }
+
+    fn binomial(n: usize, k: usize) -> f32 {
+        let k = k.min(n - k);
+        (0..k).fold(1.0, |acc, i| acc * (n - i) as f32 / (i + 1) as f32)
+    }
+
+    /// Cox-de Boor recursion computing basis functions of all degrees up to `degree`,
+    /// derivatives are computed from functions of lower degrees
+    fn evaluate_bspline(degree: usize, knots: &[f32], t: f32) -> BasisValues {
+        let count = knots.len() - degree - 1;
+        let (start, end) = (knots[degree], knots[count]);
+        let x = start + t.clamp(0.0, 1.0) * (end - start);
+
+        // Degree 0: indicator of the span containing x, the last span is closed
+        let last_span = (0..(knots.len() - 1))
+            .rev()
+            .find(|&i| knots[i] < knots[i + 1] && knots[i + 1] <= end)
+            .unwrap_or(0);
+        let lower: Vec<f32> = (0..(knots.len() - 1))
+            .map(|i| {
+                let inside = match x >= end {
+                    // Spans after the domain (unclamped knots) must not contain its end
+                    true => i == last_span,
+                    false => knots[i] <= x && x < knots[i + 1],
+                };
+                if inside {
+                    1.0
+                } else {
+                    0.0
+                }
+            })
+            .collect();
+
+        let ratio = |numerator: f32, denominator: f32| {
+            if denominator.abs() <= f32::EPSILON {
+                0.0
+            } else {
+                numerator / denominator
+            }
+        };
+        // Functions of degree `p` from functions of degree `p - 1`, `levels[p]` has degree `p`
+        let mut levels = vec![lower];
+        for p in 1..=degree {
+            let lower = &levels[p - 1];
+            let functions = knots.len() - 1 - p;
+            let values = (0..functions)
+                .map(|i| {
+                    ratio(x - knots[i], knots[i + p] - knots[i]) * lower[i]
+                        + ratio(knots[i + p + 1] - x, knots[i + p + 1] - knots[i + 1])
+                            * lower[i + 1]
+                })
+                .collect();
+            levels.push(values);
+        }
+
+        // N'(p, i) = p * (N(p - 1, i) / (t[i + p] - t[i]) - N(p - 1, i + 1) / (t[i + p + 1] - t[i + 1])),
+        // the same relation holds between derivatives of consecutive degrees
+        let differentiate = |lower: &[f32], p: usize| -> Vec<f32> {
+            (0..(knots.len() - 1 - p))
+                .map(|i| {
+                    p as f32
+                        * (ratio(lower[i], knots[i + p] - knots[i])
+                            - ratio(lower[i + 1], knots[i + p + 1] - knots[i + 1]))
+                })
+                .collect()
+        };
+        let mut derivatives = differentiate(&levels[degree - 1], degree);
+        let mut second_derivatives = match degree > 1 {
+            true => differentiate(&differentiate(&levels[degree - 2], degree - 1), degree),
+            false => vec![0.0; count],
+        };
+        // Derivatives are with respect to t, not the knot parameter
+        let scale = end - start;
+        derivatives.iter_mut().for_each(|d| *d *= scale);
+        second_derivatives
+            .iter_mut()
+            .for_each(|d| *d *= scale * scale);
+        BasisValues {
+            values: levels.pop().unwrap_or_default(),
+            derivatives,
+            second_derivatives,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOLERANCE: f32 = 1e-5;
+    const PARAMETERS: [f32; 6] = [0.0, 0.1, 0.25, 0.5, 0.77, 1.0];
+
+    fn bases() -> Vec<Basis> {
+        vec![
+            Basis::Bernstein { degree: 1 },
+            Basis::Bernstein { degree: 3 },
+            Basis::Bernstein { degree: 6 },
+            Basis::clamped_uniform(2, 5).unwrap(),
+            Basis::clamped_uniform(3, 7).unwrap(),
+            Basis::uniform(3, 6).unwrap(),
+            Basis::bspline(2, vec![0.0, 0.0, 0.0, 0.3, 0.3, 1.0, 1.0, 1.0], 5).unwrap(),
+        ]
+    }
+
+    #[test]
+    fn basis_is_partition_of_unity() {
+        for basis in bases() {
+            for t in PARAMETERS {
+                let values = basis.evaluate(t);
+                assert_eq!(values.values.len(), basis.count());
+                let sum: f32 = values.values.iter().sum();
+                let derivatives_sum: f32 = values.derivatives.iter().sum();
+                assert!(
+                    (sum - 1.0).abs() < TOLERANCE,
+                    "{:?} at {}: {}",
+                    basis,
+                    t,
+                    sum
+                );
+                assert!(
+                    derivatives_sum.abs() < 1e-3,
+                    "{:?} at {}: {}",
+                    basis,
+                    t,
+                    derivatives_sum
+                );
+                assert!(values.values.iter().all(|&value| value >= 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn clamped_bspline_without_inner_knots_equals_bernstein() {
+        for degree in 1..=5 {
+            let bernstein = Basis::Bernstein { degree };
+            let bspline = Basis::clamped_uniform(degree, degree + 1).unwrap();
+            for t in PARAMETERS {
+                let (expected, actual) = (bernstein.evaluate(t), bspline.evaluate(t));
+                for i in 0..=degree {
+                    assert!((expected.values[i] - actual.values[i]).abs() < TOLERANCE);
+                    assert!(
+                        (expected.derivatives[i] - actual.derivatives[i]).abs() < 1e-3,
+                        "degree {} at {}: {} != {}",
+                        degree,
+                        t,
+                        expected.derivatives[i],
+                        actual.derivatives[i]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn derivatives_match_finite_differences() {
+        const H: f32 = 1e-3;
+        for basis in bases() {
+            for t in [0.13, 0.5, 0.77] {
+                let (before, after) = (basis.evaluate(t - H), basis.evaluate(t + H));
+                let values = basis.evaluate(t);
+                for i in 0..basis.count() {
+                    let expected = (after.values[i] - before.values[i]) / (2.0 * H);
+                    assert!(
+                        (expected - values.derivatives[i]).abs() < 1e-2 * (1.0 + expected.abs()),
+                        "{:?} at {}: {} != {}",
+                        basis,
+                        t,
+                        expected,
+                        values.derivatives[i]
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn second_derivatives_match_finite_differences() {
+        const H: f32 = 1e-3;
+        for basis in bases() {
+            for t in [0.13, 0.5, 0.77] {
+                let (before, after) = (basis.evaluate(t - H), basis.evaluate(t + H));
+                let values = basis.evaluate(t);
+                for i in 0..basis.count() {
+                    let expected = (after.derivatives[i] - before.derivatives[i]) / (2.0 * H);
+                    assert!(
+                        (expected - values.second_derivatives[i]).abs()
+                            < 5e-2 * (1.0 + expected.abs()),
+                        "{:?} at {}: {} != {}",
+                        basis,
+                        t,
+                        expected,
+                        values.second_derivatives[i]
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt::Display;
+
+use crate::{
+    control_points::ControlPoints,
+    point::{Point, PosIn2DArr},
+};
+
+/// Boundary curve of a patch in parameter space
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PatchEdge {
+    U0,
+    U1,
+    V0,
+    V1,
+}
+
+impl PatchEdge {
+    const ALL: [PatchEdge; 4] = [PatchEdge::U0, PatchEdge::U1, PatchEdge::V0, PatchEdge::V1];
+
+    /// Number of samples along the edge in a grid of `rows x cols` samples,
+    /// where rows follow `u` and columns follow `v`
+    pub fn samples_count(&self, rows: usize, cols: usize) -> usize {
+        match self {
+            PatchEdge::U0 | PatchEdge::U1 => cols,
+            PatchEdge::V0 | PatchEdge::V1 => rows,
+        }
+    }
+
+    /// Position of the `i`-th sample along the edge in a grid of `rows x cols` samples
+    pub fn grid_position(&self, i: usize, rows: usize, cols: usize) -> PosIn2DArr {
+        match self {
+            PatchEdge::U0 => PosIn2DArr { row: 0, col: i },
+            PatchEdge::U1 => PosIn2DArr {
+                row: rows - 1,
+                col: i,
+            },
+            PatchEdge::V0 => PosIn2DArr { row: i, col: 0 },
+            PatchEdge::V1 => PosIn2DArr {
+                row: i,
+                col: cols - 1,
+            },
+        }
+    }
+
+    /// Parameters `(u, v)` of point at `t` in [0, 1] along the edge
+    fn parameters(&self, t: f32) -> (f32, f32) {
+        match self {
+            PatchEdge::U0 => (0.0, t),
+            PatchEdge::U1 => (1.0, t),
+            PatchEdge::V0 => (t, 0.0),
+            PatchEdge::V1 => (t, 1.0),
+        }
+    }
+}
+
+impl Display for PatchEdge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PatchEdge::U0 => write!(f, "u=0"),
+            PatchEdge::U1 => write!(f, "u=1"),
+            PatchEdge::V0 => write!(f, "v=0"),
+            PatchEdge::V1 => write!(f, "v=1"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Continuity {
+    /// Edges share end points, but the curves between them differ
+    Crack,
+    /// Edges coincide
+    C0,
+    /// Edges coincide and tangent planes match along them
+    G1,
+}
+
+impl Display for Continuity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Continuity::Crack => write!(f, "crack"),
+            Continuity::C0 => write!(f, "C0"),
+            Continuity::G1 => write!(f, "G1"),
+        }
+    }
+}
+
+/// Pair of patch boundaries with common end points
+#[derive(Debug, Clone, Copy)]
+pub struct SharedEdge {
+    pub patch_a: usize,
+    pub edge_a: PatchEdge,
+    pub patch_b: usize,
+    pub edge_b: PatchEdge,
+    /// Edge B runs from the end of edge A to its start
+    pub reversed: bool,
+    pub continuity: Continuity,
+}
+
+impl SharedEdge {
+    const SAMPLES: usize = 9;
+    const POSITION_TOLERANCE: f32 = 0.5;
+    // cos(1 degree)
+    const NORMAL_TOLERANCE: f32 = 0.9998;
+
+    /// Finds all pairs of patch boundaries that share end points and classifies
+    /// continuity across them by sampling both boundary curves
+    pub fn find_all(patches: &[ControlPoints]) -> Vec<SharedEdge> {
+        let samples: Vec<Vec<Vec<Point>>> = patches
+            .iter()
+            .map(|patch| {
+                PatchEdge::ALL
+                    .iter()
+                    .map(|edge| Self::sample_edge(patch, *edge))
+                    .collect()
+            })
+            .collect();
+
+        let mut shared_edges = vec![];
+        for patch_a in 0..patches.len() {
+            for patch_b in (patch_a + 1)..patches.len() {
+                for (id_a, edge_a) in PatchEdge::ALL.iter().enumerate() {
+                    for (id_b, edge_b) in PatchEdge::ALL.iter().enumerate() {
+                        let a = &samples[patch_a][id_a];
+                        let b = &samples[patch_b][id_b];
+                        if let Some((continuity, reversed)) = Self::classify(a, b) {
+                            shared_edges.push(SharedEdge {
+                                patch_a,
+                                edge_a: *edge_a,
+                                patch_b,
+                                edge_b: *edge_b,
+                                reversed,
+                                continuity,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        shared_edges
+    }
+
+    fn sample_edge(patch: &ControlPoints, edge: PatchEdge) -> Vec<Point> {
+        (0..Self::SAMPLES)
+            .map(|i| {
+                let t = i as f32 / (Self::SAMPLES - 1) as f32;
+                let (u, v) = edge.parameters(t);
+                Point::from_control_points(u, v, patch)
+            })
+            .collect()
+    }
+
+    fn is_collapsed(edge: &[Point]) -> bool {
+        let first = edge[0].before_rotation().p();
+        edge.iter()
+            .all(|point| (point.before_rotation().p() - first).norm() <= Self::POSITION_TOLERANCE)
+    }
+
+    /// Returns continuity and whether `b` is reversed relative to `a`, or `None`
+    /// if edges don't share end points or one of them is collapsed to a point
+    fn classify(a: &[Point], b: &[Point]) -> Option<(Continuity, bool)> {
+        if Self::is_collapsed(a) || Self::is_collapsed(b) {
+            return None;
+        }
+        let last = a.len() - 1;
+        let same_point = |i: usize, j: usize| {
+            (a[i].before_rotation().p() - b[j].before_rotation().p()).norm()
+                <= Self::POSITION_TOLERANCE
+        };
+        let reversed = match (same_point(0, 0) && same_point(last, last))
+            || (same_point(0, last) && same_point(last, 0))
+        {
+            true => same_point(0, last) && !same_point(0, 0),
+            false => return None,
+        };
+        let other = |i: usize| match reversed {
+            true => last - i,
+            false => i,
+        };
+
+        if !(0..a.len()).all(|i| same_point(i, other(i))) {
+            return Some((Continuity::Crack, reversed));
+        }
+        let normals_match = (0..a.len()).all(|i| {
+            let n_a = a[i].before_rotation().n();
+            let n_b = b[other(i)].before_rotation().n();
+            n_a.dot(&n_b).abs() >= Self::NORMAL_TOLERANCE
+        });
+        let continuity = match normals_match {
+            true => Continuity::G1,
+            false => Continuity::C0,
+        };
+        Some((continuity, reversed))
+    }
+}
@@ -0,0 +1,123 @@
+use egui::{Pos2, Vec2};
+use nalgebra::{Matrix3, Vector3};
+
+use crate::{
+    control_points::ControlPoints, rotations::Rotations, triangle_mesh_filler::ControlsState,
+};
+
+/// Position of a single control point among all loaded patches
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ControlPointId {
+    pub patch: usize,
+    pub row: usize,
+    pub col: usize,
+}
+
+/// Picking and dragging of control points in the viewport. Dragging moves the point
+/// in the view plane, with shift held vertical movement of the mouse moves it along view Z.
+#[derive(Debug, Default)]
+pub struct ControlPointEditor {
+    hovered: Option<ControlPointId>,
+    selected: Option<ControlPointId>,
+    dragging: bool,
+}
+
+impl ControlPointEditor {
+    /// Maximum distance in pixels between cursor and control point to pick it
+    const PICK_RADIUS: f32 = 8.0;
+
+    pub fn hovered(&self) -> Option<ControlPointId> {
+        self.hovered
+    }
+
+    pub fn selected(&self) -> Option<ControlPointId> {
+        self.selected
+    }
+
+    /// Updates hover and selection from viewport `response` and moves dragged control point.
+    /// Returns `true` if any control point has changed.
+    pub fn handle_input(
+        &mut self,
+        response: &egui::Response,
+        screen_center: Pos2,
+        patches: &mut [ControlPoints],
+        controls_state: &ControlsState,
+    ) -> bool {
+        let rotation =
+            Rotations::create_rotation_matrix(controls_state.alfa(), controls_state.beta());
+        self.hovered = response
+            .hover_pos()
+            .and_then(|pos| Self::pick(pos, screen_center, patches, &rotation));
+
+        if response.drag_started() {
+            let origin = response.ctx.input(|i| i.pointer.press_origin());
+            self.selected =
+                origin.and_then(|pos| Self::pick(pos, screen_center, patches, &rotation));
+            self.dragging = self.selected.is_some();
+        } else if response.clicked() {
+            self.selected = self.hovered;
+        }
+        if response.drag_stopped() {
+            self.dragging = false;
+        }
+
+        let mut changed = false;
+        if let (true, Some(id)) = (self.dragging && response.dragged(), self.selected) {
+            let delta = response.drag_delta();
+            let along_z = response.ctx.input(|i| i.modifiers.shift);
+            if delta != Vec2::ZERO {
+                // Screen Y axis points down, while Y axis of the view points up
+                let view_delta = match along_z {
+                    true => Vector3::new(0.0, 0.0, -delta.y),
+                    false => Vector3::new(delta.x, -delta.y, 0.0),
+                };
+                // Rotation matrix is orthogonal, so its transpose is its inverse
+                let world_delta = rotation.transpose() * view_delta;
+                let control_points = &mut patches[id.patch];
+                let point = control_points.at(id.row, id.col) + world_delta;
+                control_points.set_at(id.row, id.col, point);
+                changed = true;
+            }
+        }
+
+        match (self.dragging, self.hovered.is_some()) {
+            (true, _) => response.ctx.set_cursor_icon(egui::CursorIcon::Grabbing),
+            (false, true) => response.ctx.set_cursor_icon(egui::CursorIcon::Grab),
+            (false, false) => {}
+        }
+        changed
+    }
+
+    /// Returns the closest control point within `PICK_RADIUS`, preferring the one nearer to the viewer
+    fn pick(
+        pos: Pos2,
+        screen_center: Pos2,
+        patches: &[ControlPoints],
+        rotation: &Matrix3<f32>,
+    ) -> Option<ControlPointId> {
+        let mut best: Option<(ControlPointId, f32, f32)> = None;
+        for (patch, control_points) in patches.iter().enumerate() {
+            for row in 0..control_points.rows() {
+                for col in 0..control_points.cols() {
+                    let point = rotation * control_points.at(row, col);
+                    let screen = Pos2::new(screen_center.x + point.x, screen_center.y - point.y);
+                    let distance = screen.distance(pos);
+                    if distance > Self::PICK_RADIUS {
+                        continue;
+                    }
+                    let is_better = match best {
+                        None => true,
+                        Some((_, best_distance, best_z)) => {
+                            distance < best_distance - 0.5
+                                || (distance < best_distance + 0.5 && point.z > best_z)
+                        }
+                    };
+                    if is_better {
+                        best = Some((ControlPointId { patch, row, col }, distance, point.z));
+                    }
+                }
+            }
+        }
+        best.map(|(id, _, _)| id)
+    }
+}
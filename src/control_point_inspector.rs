@@ -0,0 +1,246 @@
+use nalgebra::Vector3;
+
+use crate::{control_point_editor::ControlPointId, control_points::ControlPoints};
+
+/// Part of the control net affected by inspector operations
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum NetSelection {
+    Row,
+    Column,
+    Whole,
+}
+
+impl NetSelection {
+    pub const ALL: [NetSelection; 3] =
+        [NetSelection::Row, NetSelection::Column, NetSelection::Whole];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            NetSelection::Row => "Row",
+            NetSelection::Column => "Column",
+            NetSelection::Whole => "Whole net",
+        }
+    }
+}
+
+/// Numeric editing of control points of a single patch
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ControlPointInspector {
+    patch: usize,
+    selection: NetSelection,
+    index: usize,
+    scale: f32,
+    offset: Vector3<f32>,
+}
+
+impl Default for ControlPointInspector {
+    fn default() -> Self {
+        Self {
+            patch: 0,
+            selection: NetSelection::Row,
+            index: 0,
+            scale: 1.0,
+            offset: Vector3::zeros(),
+        }
+    }
+}
+
+impl ControlPointInspector {
+    const FIELD_SPEED: f64 = 1.0;
+    const SPACING_Y: f32 = 4.0;
+
+    /// Shows editable coordinates of the inspected patch and operations on its rows and columns.
+    /// `originals` are patches as loaded from the file. Returns `true` if any control point has changed.
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        patches: &mut [ControlPoints],
+        originals: &[ControlPoints],
+        selected: Option<ControlPointId>,
+    ) -> bool {
+        if patches.is_empty() {
+            return false;
+        }
+        self.patch = self.patch.min(patches.len() - 1);
+        let mut changed = false;
+        ui.spacing_mut().item_spacing.y = Self::SPACING_Y;
+
+        ui.horizontal(|ui| {
+            if patches.len() > 1 {
+                ui.add(
+                    egui::DragValue::new(&mut self.patch)
+                        .range(0..=(patches.len() - 1))
+                        .prefix("Patch "),
+                );
+                if let Some(id) = selected {
+                    if id.patch != self.patch && ui.button("Go to selected").clicked() {
+                        self.patch = id.patch;
+                    }
+                }
+            }
+            if ui.button("Reset to loaded file").clicked() {
+                patches[self.patch] = originals[self.patch].clone();
+                changed = true;
+            }
+        });
+
+        let control_points = &mut patches[self.patch];
+        changed |= Self::show_grid(ui, self.patch, control_points, selected);
+        changed |= self.show_operations(ui, control_points);
+        changed
+    }
+
+    fn show_grid(
+        ui: &mut egui::Ui,
+        patch: usize,
+        control_points: &mut ControlPoints,
+        selected: Option<ControlPointId>,
+    ) -> bool {
+        let mut changed = false;
+        egui::ScrollArea::both()
+            .id_salt("ControlPointsGrid")
+            .max_height(200.0)
+            .show(ui, |ui| {
+                egui::Grid::new("ControlPointsGrid")
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label("");
+                        for col in 0..control_points.cols() {
+                            ui.label(format!("col {}", col));
+                        }
+                        ui.end_row();
+                        for row in 0..control_points.rows() {
+                            ui.label(format!("row {}", row));
+                            for col in 0..control_points.cols() {
+                                let mut point = control_points.at(row, col);
+                                let frame =
+                                    match selected == Some(ControlPointId { patch, row, col }) {
+                                        true => egui::Frame::none()
+                                            .stroke(egui::Stroke::new(1.0, egui::Color32::RED)),
+                                        false => egui::Frame::none(),
+                                    };
+                                frame.show(ui, |ui| {
+                                    ui.vertical(|ui| {
+                                        for (coordinate, name) in
+                                            point.iter_mut().zip(["x: ", "y: ", "z: "])
+                                        {
+                                            changed |= ui
+                                                .add(
+                                                    egui::DragValue::new(coordinate)
+                                                        .speed(Self::FIELD_SPEED)
+                                                        .prefix(name),
+                                                )
+                                                .changed();
+                                        }
+                                    });
+                                });
+                                if point != control_points.at(row, col) {
+                                    control_points.set_at(row, col, point);
+                                }
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+        changed
+    }
+
+    fn show_operations(&mut self, ui: &mut egui::Ui, control_points: &mut ControlPoints) -> bool {
+        let mut changed = false;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_salt("NetSelection")
+                .selected_text(self.selection.label())
+                .show_ui(ui, |ui| {
+                    for selection in NetSelection::ALL {
+                        ui.selectable_value(&mut self.selection, selection, selection.label());
+                    }
+                });
+            let max_index = match self.selection {
+                NetSelection::Row => control_points.rows() - 1,
+                NetSelection::Column => control_points.cols() - 1,
+                NetSelection::Whole => 0,
+            };
+            self.index = self.index.min(max_index);
+            ui.add_enabled(
+                self.selection != NetSelection::Whole,
+                egui::DragValue::new(&mut self.index).range(0..=max_index),
+            );
+            if ui
+                .button("Flatten Z")
+                .on_hover_text("Sets Z of all selected points to their average")
+                .clicked()
+            {
+                self.transform(control_points, |point, centroid| {
+                    Vector3::new(point.x, point.y, centroid.z)
+                });
+                changed = true;
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut self.scale)
+                    .speed(0.01)
+                    .range(0.0..=10.0)
+                    .prefix("Factor "),
+            );
+            if ui
+                .button("Scale")
+                .on_hover_text("Scales selected points around their centroid")
+                .clicked()
+            {
+                let scale = self.scale;
+                self.transform(control_points, |point, centroid| {
+                    centroid + (point - centroid) * scale
+                });
+                changed = true;
+            }
+        });
+        ui.horizontal(|ui| {
+            for (coordinate, name) in self.offset.iter_mut().zip(["x: ", "y: ", "z: "]) {
+                ui.add(
+                    egui::DragValue::new(coordinate)
+                        .speed(Self::FIELD_SPEED)
+                        .prefix(name),
+                );
+            }
+            if ui.button("Translate").clicked() {
+                let offset = self.offset;
+                self.transform(control_points, |point, _| point + offset);
+                changed = true;
+            }
+        });
+        changed
+    }
+
+    /// Grid positions `(row, col)` of points affected by operations
+    fn positions(&self, control_points: &ControlPoints) -> Vec<(usize, usize)> {
+        match self.selection {
+            NetSelection::Row => (0..control_points.cols())
+                .map(|col| (self.index, col))
+                .collect(),
+            NetSelection::Column => (0..control_points.rows())
+                .map(|row| (row, self.index))
+                .collect(),
+            NetSelection::Whole => (0..control_points.rows())
+                .flat_map(|row| (0..control_points.cols()).map(move |col| (row, col)))
+                .collect(),
+        }
+    }
+
+    /// Replaces each selected point with `f(point, centroid of selected points)`
+    fn transform<F>(&self, control_points: &mut ControlPoints, f: F)
+    where
+        F: Fn(Vector3<f32>, Vector3<f32>) -> Vector3<f32>,
+    {
+        let positions = self.positions(control_points);
+        let centroid = positions
+            .iter()
+            .map(|&(row, col)| control_points.at(row, col))
+            .sum::<Vector3<f32>>()
+            / positions.len() as f32;
+        for (row, col) in positions {
+            let point = f(control_points.at(row, col), centroid);
+            control_points.set_at(row, col, point);
+        }
+    }
+}
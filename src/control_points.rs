@@ -1,52 +1,482 @@
 use std::{
     fs::File,
-    io::{BufRead, BufReader},
+    io::{BufRead, BufReader, BufWriter, Write},
     path::Path,
+    str::{FromStr, SplitWhitespace},
 };
 
 use anyhow::{Context, Error, Result};
 use nalgebra::Vector3;
 
-const CONTROL_POINTS_COUNT: usize = 16;
-pub const CONTROL_POINT_ROWS: usize = 4;
-pub const CONTROL_POINT_COLS: usize = 4;
+use crate::{basis::Basis, geometry::Geometry};
 
+/// Grid of control points of a patch. Rows correspond to parameter `u` and columns to parameter `v`.
+/// By default it is a Bézier patch of degree `(rows - 1, cols - 1)`, but each control point can
+/// have weight (rational patch) and both directions can use B-spline basis (NURBS patch).
+#[derive(Clone, PartialEq)]
 pub struct ControlPoints {
-    points: [Vector3<f32>; CONTROL_POINTS_COUNT],
+    points: Vec<Vector3<f32>>,
+    weights: Vec<f32>,
+    rows: usize,
+    cols: usize,
+    basis_u: Basis,
+    basis_v: Basis,
 }
 
 impl ControlPoints {
-    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+    pub const MIN_SIZE: usize = 2;
+
+    pub fn new(points: Vec<Vector3<f32>>, rows: usize, cols: usize) -> Result<Self> {
+        let weights = vec![1.0; points.len()];
+        Self::with_basis(
+            points,
+            weights,
+            rows,
+            cols,
+            Basis::Bernstein {
+                degree: rows.saturating_sub(1),
+            },
+            Basis::Bernstein {
+                degree: cols.saturating_sub(1),
+            },
+        )
+    }
+
+    pub fn with_basis(
+        points: Vec<Vector3<f32>>,
+        weights: Vec<f32>,
+        rows: usize,
+        cols: usize,
+        basis_u: Basis,
+        basis_v: Basis,
+    ) -> Result<Self> {
+        if rows < Self::MIN_SIZE || cols < Self::MIN_SIZE {
+            return Err(Error::msg(format!(
+                "Invalid grid dimensions {}x{} (each must be at least {})",
+                rows,
+                cols,
+                Self::MIN_SIZE
+            )));
+        }
+        if points.len() != rows * cols {
+            return Err(Error::msg(format!(
+                "Invalid number of points (got {}, expected {})",
+                points.len(),
+                rows * cols
+            )));
+        }
+        if weights.len() != points.len() || weights.iter().any(|&w| w <= 0.0) {
+            return Err(Error::msg("Each control point must have positive weight"));
+        }
+        if basis_u.count() != rows || basis_v.count() != cols {
+            return Err(Error::msg(format!(
+                "Basis functions count ({}x{}) does not match grid dimensions {}x{}",
+                basis_u.count(),
+                basis_v.count(),
+                rows,
+                cols
+            )));
+        }
+        Ok(Self {
+            points,
+            weights,
+            rows,
+            cols,
+            basis_u,
+            basis_v,
+        })
+    }
+
+    /// Each line of the file contains `x y z` (or `x y z w` for weighted point) of a single
+    /// control point, row by row. Optionally the first line of a patch can declare grid
+    /// dimensions as `rows cols`, otherwise the grid is assumed to be square.
+    /// NURBS patch is declared by `degree du dv` line and optional `knots_u ...`
+    /// and `knots_v ...` lines (clamped uniform knots are used if omitted).
+    /// `bspline` line declares uniform cubic B-spline patch over the whole grid.
+    /// File can contain multiple patches separated by empty lines.
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Vec<Self>> {
         let file = File::open(path).context("Cannot open file")?;
         let reader = BufReader::new(file);
-        let mut points = Vec::with_capacity(CONTROL_POINTS_COUNT);
+        let mut patches = vec![];
+        let mut lines = vec![];
         for line in reader.lines() {
             let line = line?;
+            if line.trim().is_empty() {
+                if !lines.is_empty() {
+                    patches.push(Self::parse(&lines)?);
+                    lines.clear();
+                }
+            } else {
+                lines.push(line);
+            }
+        }
+        if !lines.is_empty() {
+            patches.push(Self::parse(&lines)?);
+        }
+        if patches.is_empty() {
+            return Err(Error::msg("File does not contain any control points"));
+        }
+        Ok(patches)
+    }
+
+    /// Writes patches in the format read by `load_from_file`, separated by empty lines
+    pub fn save_to_file<P: AsRef<Path>>(patches: &[Self], path: P) -> Result<()> {
+        let file = File::create(path).context("Cannot create file")?;
+        let mut writer = BufWriter::new(file);
+        for (i, patch) in patches.iter().enumerate() {
+            if i > 0 {
+                writeln!(writer)?;
+            }
+            patch.write(&mut writer)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writeln!(writer, "{} {}", self.rows, self.cols)?;
+        let is_bezier = matches!(
+            (&self.basis_u, &self.basis_v),
+            (Basis::Bernstein { .. }, Basis::Bernstein { .. })
+        );
+        if self.is_uniform_bspline() {
+            writeln!(writer, "bspline")?;
+        } else if !is_bezier {
+            // Knots are always written explicitly, so the basis is restored exactly
+            writeln!(
+                writer,
+                "degree {} {}",
+                self.basis_u.degree(),
+                self.basis_v.degree()
+            )?;
+            for (keyword, basis) in [("knots_u", &self.basis_u), ("knots_v", &self.basis_v)] {
+                let knots: Vec<String> = basis.knots().iter().map(|k| k.to_string()).collect();
+                writeln!(writer, "{} {}", keyword, knots.join(" "))?;
+            }
+        }
+        for (point, weight) in self.points.iter().zip(self.weights.iter()) {
+            match *weight == 1.0 {
+                true => writeln!(writer, "{} {} {}", point.x, point.y, point.z)?,
+                false => writeln!(writer, "{} {} {} {}", point.x, point.y, point.z, weight)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Patch uses the same basis as one declared by `bspline` line
+    pub fn is_uniform_bspline(&self) -> bool {
+        Basis::uniform(Self::BSPLINE_DEGREE, self.rows)
+            .ok()
+            .as_ref()
+            == Some(&self.basis_u)
+            && Basis::uniform(Self::BSPLINE_DEGREE, self.cols)
+                .ok()
+                .as_ref()
+                == Some(&self.basis_v)
+    }
+
+    /// Reads the `.bpt` format used by classic datasets (e.g. Utah teapot):
+    /// number of patches, then for each patch its degrees `n m` followed by
+    /// `(n + 1) * (m + 1)` control points `x y z`.
+    pub fn load_from_bpt_file<P: AsRef<Path>>(path: P) -> Result<Vec<Self>> {
+        let content = std::fs::read_to_string(path).context("Cannot open file")?;
+        let mut tokens = content.split_whitespace();
+        // Counts come from the file, so nothing is preallocated from them. A too large
+        // count fails at the end of the file instead of exhausting memory.
+        let patches_count: usize = Self::next_bpt_token(&mut tokens, "number of patches")?;
+        let mut patches = vec![];
+        for _ in 0..patches_count {
+            let degree_u: usize = Self::next_bpt_token(&mut tokens, "patch degree")?;
+            let degree_v: usize = Self::next_bpt_token(&mut tokens, "patch degree")?;
+            let (rows, cols) = (degree_u.saturating_add(1), degree_v.saturating_add(1));
+            let points_count = rows.checked_mul(cols).ok_or_else(|| {
+                Error::msg(format!(
+                    "Patch degrees {} {} are too large",
+                    degree_u, degree_v
+                ))
+            })?;
+            let mut points = vec![];
+            for _ in 0..points_count {
+                let x = Self::next_bpt_token(&mut tokens, "coordinate")?;
+                let y = Self::next_bpt_token(&mut tokens, "coordinate")?;
+                let z = Self::next_bpt_token(&mut tokens, "coordinate")?;
+                points.push(Vector3::new(x, y, z));
+            }
+            patches.push(Self::new(points, rows, cols)?);
+        }
+        if patches.is_empty() {
+            return Err(Error::msg("File does not contain any patches"));
+        }
+        Ok(patches)
+    }
+
+    /// Parses the next token of a `.bpt` file. Counts are parsed as `usize`,
+    /// so fractional or negative values are rejected instead of truncated.
+    fn next_bpt_token<T: FromStr>(tokens: &mut SplitWhitespace, what: &str) -> Result<T> {
+        let token = tokens
+            .next()
+            .ok_or_else(|| Error::msg(format!("Unexpected end of file, expected {}", what)))?;
+        token
+            .parse::<T>()
+            .map_err(|_| Error::msg(format!("Invalid {}: {}", what, token)))
+    }
+
+    /// Centers and scales all patches as a whole, so `.bpt` datasets given in arbitrary
+    /// units fit into `size`
+    pub fn fit_to_size(patches: &mut [Self], size: f32) {
+        let points = patches.iter_mut().flat_map(|patch| patch.points.iter_mut());
+        Geometry::fit_to_size(points, size);
+    }
+
+    const BSPLINE_DEGREE: usize = 3;
+
+    fn parse(lines: &[String]) -> Result<Self> {
+        let mut dimensions = None;
+        let mut uniform_bspline = false;
+        let mut degrees = None;
+        let mut knots_u = None;
+        let mut knots_v = None;
+        let mut points = vec![];
+        let mut weights = vec![];
+        for line in lines {
+            match line.split_whitespace().next() {
+                Some("degree") => {
+                    degrees = Some(Self::parse_dimensions(line)?);
+                    continue;
+                }
+                Some("bspline") => {
+                    uniform_bspline = true;
+                    continue;
+                }
+                Some("knots_u") => {
+                    knots_u = Some(Self::parse_knots(line)?);
+                    continue;
+                }
+                Some("knots_v") => {
+                    knots_v = Some(Self::parse_knots(line)?);
+                    continue;
+                }
+                _ => {}
+            }
             let coord: Vec<f32> = line
                 .split_whitespace()
                 .flat_map(|s| s.parse::<f32>())
                 .collect();
-            if coord.len() == 3 {
-                points.push(Vector3::from_vec(coord));
-            } else {
-                return Err(Error::msg(format!("Invalid coords: {}", line)));
+            match coord.len() {
+                3 => {
+                    points.push(Vector3::from_vec(coord));
+                    weights.push(1.0);
+                }
+                4 => {
+                    points.push(Vector3::new(coord[0], coord[1], coord[2]));
+                    weights.push(coord[3]);
+                }
+                2 if points.is_empty() && dimensions.is_none() => {
+                    dimensions = Some(Self::parse_dimensions(line)?)
+                }
+                _ => return Err(Error::msg(format!("Invalid coords: {}", line))),
+            }
+        }
+        let (rows, cols) = match dimensions {
+            Some(dimensions) => dimensions,
+            None => Self::infer_dimensions(points.len())?,
+        };
+        let (basis_u, basis_v) = match degrees {
+            _ if uniform_bspline
+                && (degrees.is_some() || knots_u.is_some() || knots_v.is_some()) =>
+            {
+                return Err(Error::msg(
+                    "Uniform B-spline patch cannot declare degree or knots",
+                ));
+            }
+            _ if uniform_bspline => (
+                Basis::uniform(Self::BSPLINE_DEGREE, rows)?,
+                Basis::uniform(Self::BSPLINE_DEGREE, cols)?,
+            ),
+            Some((degree_u, degree_v)) => {
+                let basis_u = match knots_u {
+                    Some(knots) => Basis::bspline(degree_u, knots, rows)?,
+                    None => Basis::clamped_uniform(degree_u, rows)?,
+                };
+                let basis_v = match knots_v {
+                    Some(knots) => Basis::bspline(degree_v, knots, cols)?,
+                    None => Basis::clamped_uniform(degree_v, cols)?,
+                };
+                (basis_u, basis_v)
+            }
+            None if knots_u.is_some() || knots_v.is_some() => {
+                return Err(Error::msg("Knots require `degree du dv` line"));
             }
+            None => (
+                Basis::Bernstein {
+                    degree: rows.saturating_sub(1),
+                },
+                Basis::Bernstein {
+                    degree: cols.saturating_sub(1),
+                },
+            ),
+        };
+        Self::with_basis(points, weights, rows, cols, basis_u, basis_v)
+    }
+
+    fn parse_knots(line: &str) -> Result<Vec<f32>> {
+        line.split_whitespace()
+            .skip(1)
+            .map(|s| {
+                s.parse::<f32>()
+                    .map_err(|_| Error::msg(format!("Invalid knot: {}", s)))
+            })
+            .collect()
+    }
+
+    fn parse_dimensions(line: &str) -> Result<(usize, usize)> {
+        let dimensions: Vec<usize> = line
+            .split_whitespace()
+            .flat_map(|s| s.parse::<usize>())
+            .collect();
+        match dimensions[..] {
+            [rows, cols] => Ok((rows, cols)),
+            _ => Err(Error::msg(format!("Invalid dimensions: {}", line))),
         }
-        if points.len() != CONTROL_POINTS_COUNT {
+    }
+
+    fn infer_dimensions(points_count: usize) -> Result<(usize, usize)> {
+        let size = (points_count as f32).sqrt().round() as usize;
+        if size * size != points_count {
             return Err(Error::msg(format!(
-                "Invalid number of points (got {}, expected {})",
-                points.len(),
-                CONTROL_POINTS_COUNT
+                "Cannot infer square grid from {} points, declare `rows cols` in the first line",
+                points_count
             )));
         }
-
-        let points: [Vector3<f32>; CONTROL_POINTS_COUNT] = points
-            .try_into()
-            .map_err(|_| Error::msg("Cannot parse vec to array"))?;
-        Ok(Self { points })
+        Ok((size, size))
     }
 
     pub fn at(&self, row: usize, col: usize) -> Vector3<f32> {
-        self.points[row * CONTROL_POINT_COLS + col]
+        self.points[row * self.cols + col]
+    }
+
+    pub fn set_at(&mut self, row: usize, col: usize, point: Vector3<f32>) {
+        self.points[row * self.cols + col] = point;
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Average length of control polygons along `u` (down each column) and along `v` (along each row)
+    pub fn control_net_lengths(&self) -> (f32, f32) {
+        let length_u: f32 = (0..self.cols)
+            .map(|col| {
+                (1..self.rows)
+                    .map(|row| (self.at(row, col) - self.at(row - 1, col)).norm())
+                    .sum::<f32>()
+            })
+            .sum();
+        let length_v: f32 = (0..self.rows)
+            .map(|row| {
+                (1..self.cols)
+                    .map(|col| (self.at(row, col) - self.at(row, col - 1)).norm())
+                    .sum::<f32>()
+            })
+            .sum();
+        (length_u / self.cols as f32, length_v / self.rows as f32)
+    }
+
+    pub fn weight(&self, row: usize, col: usize) -> f32 {
+        self.weights[row * self.cols + col]
+    }
+
+    pub fn basis_u(&self) -> &Basis {
+        &self.basis_u
+    }
+
+    pub fn basis_v(&self) -> &Basis {
+        &self.basis_v
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::mesh::fixtures::temp_path;
+
+    fn load_bpt(name: &str, content: &str) -> Result<Vec<ControlPoints>> {
+        let path = temp_path(name);
+        fs::write(&path, content).unwrap();
+        let patches = ControlPoints::load_from_bpt_file(&path);
+        fs::remove_file(&path).unwrap();
+        patches
+    }
+
+    #[test]
+    fn saved_patches_are_loaded_unchanged() {
+        let mut patches = vec![];
+        for file in [
+            // Plain Bézier patches, square and rectangular
+            "config/default_config.txt",
+            "config/wavy_3x5_config.txt",
+            // Rational NURBS patch with explicit knots
+            "config/half_cylinder_nurbs.txt",
+            // Uniform cubic B-spline patch
+            "config/bspline_6x7_config.txt",
+        ] {
+            patches.extend(ControlPoints::load_from_file(file).unwrap());
+        }
+        assert!(patches
+            .iter()
+            .any(|patch| patch.weights.iter().any(|&w| w != 1.0)));
+        assert!(patches.iter().any(ControlPoints::is_uniform_bspline));
+
+        let path = temp_path("saved_patches.txt");
+        ControlPoints::save_to_file(&patches, &path).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        let loaded = ControlPoints::load_from_file(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.len(), patches.len());
+        assert_eq!(content.lines().filter(|line| *line == "bspline").count(), 1);
+        for (original, loaded) in patches.iter().zip(loaded.iter()) {
+            assert_eq!((loaded.rows, loaded.cols), (original.rows, original.cols));
+            assert_eq!(loaded.points, original.points);
+            assert_eq!(loaded.weights, original.weights);
+            assert_eq!(loaded.basis_u.degree(), original.basis_u.degree());
+            assert_eq!(loaded.basis_v.degree(), original.basis_v.degree());
+            assert_eq!(loaded.basis_u.knots(), original.basis_u.knots());
+            assert_eq!(loaded.basis_v.knots(), original.basis_v.knots());
+            assert_eq!(loaded.is_uniform_bspline(), original.is_uniform_bspline());
+            assert!(loaded == original);
+        }
+    }
+
+    #[test]
+    fn bpt_loads_bundled_file() {
+        let patches = ControlPoints::load_from_bpt_file("config/two_patches.bpt").unwrap();
+        assert_eq!(patches.len(), 2);
+    }
+
+    #[test]
+    fn bpt_rejects_invalid_counts() {
+        let points = "0 0 0\n".repeat(4);
+        assert!(load_bpt("bpt_valid", &format!("1\n1 1\n{}", points)).is_ok());
+        for (name, header) in [
+            ("bpt_fraction", "1\n2.7 1\n"),
+            ("bpt_negative", "1\n-1 1\n"),
+            ("bpt_huge_count", "1e30\n1 1\n"),
+            ("bpt_huge_degree", "1\n99999999999 99999999999\n"),
+            ("bpt_overflow", &format!("1\n{} 1\n", usize::MAX)),
+            ("bpt_missing_patches", "1000000000000\n1 1\n"),
+        ] {
+            assert!(
+                load_bpt(name, &format!("{}{}", header, points)).is_err(),
+                "{} was accepted",
+                name
+            );
+        }
     }
 }
@@ -0,0 +1,183 @@
+use std::ops::{AddAssign, Mul};
+
+use egui::Color32;
+use nalgebra::Vector3;
+
+/// Curvatures of the surface at a single point. They don't depend on rotation of the surface,
+/// but their sign depends on orientation of the normal.
+#[derive(Debug, Clone, Copy)]
+pub struct Curvatures {
+    pub gaussian: f32,
+    pub mean: f32,
+    /// Larger principal curvature
+    pub max_principal: f32,
+    /// Smaller principal curvature
+    pub min_principal: f32,
+}
+
+impl Curvatures {
+    pub const ZERO: Self = Self {
+        gaussian: 0.0,
+        mean: 0.0,
+        max_principal: 0.0,
+        min_principal: 0.0,
+    };
+
+    /// Computes curvatures from the first and second fundamental forms, given by partial
+    /// derivatives of the surface and its unit normal. Returns zeros if tangents are parallel.
+    pub fn from_derivatives(
+        pu: Vector3<f32>,
+        pv: Vector3<f32>,
+        puu: Vector3<f32>,
+        puv: Vector3<f32>,
+        pvv: Vector3<f32>,
+        n: Vector3<f32>,
+    ) -> Self {
+        let (e, f, g) = (pu.dot(&pu), pu.dot(&pv), pv.dot(&pv));
+        let determinant = e * g - f * f;
+        if determinant <= f32::EPSILON * e * g || n == Vector3::zeros() {
+            return Self::ZERO;
+        }
+        let (l, m, nn) = (puu.dot(&n), puv.dot(&n), pvv.dot(&n));
+        let gaussian = (l * nn - m * m) / determinant;
+        let mean = (e * nn - 2.0 * f * m + g * l) / (2.0 * determinant);
+        // Principal curvatures are roots of k^2 - 2Hk + K = 0
+        let discriminant = (mean * mean - gaussian).max(0.0).sqrt();
+        Self {
+            gaussian,
+            mean,
+            max_principal: mean + discriminant,
+            min_principal: mean - discriminant,
+        }
+    }
+}
+
+impl Mul<f32> for Curvatures {
+    type Output = Curvatures;
+
+    fn mul(self, rhs: f32) -> Self::Output {
+        Curvatures {
+            gaussian: self.gaussian * rhs,
+            mean: self.mean * rhs,
+            max_principal: self.max_principal * rhs,
+            min_principal: self.min_principal * rhs,
+        }
+    }
+}
+
+impl AddAssign<Curvatures> for Curvatures {
+    fn add_assign(&mut self, rhs: Curvatures) {
+        self.gaussian += rhs.gaussian;
+        self.mean += rhs.mean;
+        self.max_principal += rhs.max_principal;
+        self.min_principal += rhs.min_principal;
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CurvatureKind {
+    Gaussian,
+    Mean,
+    MaxPrincipal,
+    MinPrincipal,
+}
+
+impl CurvatureKind {
+    pub const ALL: [CurvatureKind; 4] = [
+        CurvatureKind::Gaussian,
+        CurvatureKind::Mean,
+        CurvatureKind::MaxPrincipal,
+        CurvatureKind::MinPrincipal,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CurvatureKind::Gaussian => "Gaussian",
+            CurvatureKind::Mean => "Mean",
+            CurvatureKind::MaxPrincipal => "Max principal",
+            CurvatureKind::MinPrincipal => "Min principal",
+        }
+    }
+
+    pub fn value(&self, curvatures: &Curvatures) -> f32 {
+        match self {
+            CurvatureKind::Gaussian => curvatures.gaussian,
+            CurvatureKind::Mean => curvatures.mean,
+            CurvatureKind::MaxPrincipal => curvatures.max_principal,
+            CurvatureKind::MinPrincipal => curvatures.min_principal,
+        }
+    }
+}
+
+/// Maps chosen curvature from `[min, max]` onto a blue-cyan-green-yellow-red color scale.
+/// Values outside of the range get the color of the nearer end.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct CurvatureMap {
+    pub kind: CurvatureKind,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl CurvatureMap {
+    const SCALE: [Color32; 5] = [
+        Color32::from_rgb(0, 0, 255),
+        Color32::from_rgb(0, 255, 255),
+        Color32::from_rgb(0, 255, 0),
+        Color32::from_rgb(255, 255, 0),
+        Color32::from_rgb(255, 0, 0),
+    ];
+    /// Fraction of values at each end ignored by `fit_range`, so a few extreme
+    /// values (e.g. near degenerate points) don't squash the rest of the scale
+    const OUTLIER_FRACTION: f32 = 0.02;
+
+    pub fn color(&self, curvatures: &Curvatures) -> Color32 {
+        let value = self.kind.value(curvatures);
+        let t = match self.max > self.min {
+            true => (value - self.min) / (self.max - self.min),
+            false => 0.5,
+        };
+        Self::scale_color(t)
+    }
+
+    /// Color at position `t` in [0, 1] of the scale, used also to draw the legend
+    pub fn scale_color(t: f32) -> Color32 {
+        let position = t.clamp(0.0, 1.0) * (Self::SCALE.len() - 1) as f32;
+        let i = (position.floor() as usize).min(Self::SCALE.len() - 2);
+        let fraction = position - i as f32;
+        let (from, to) = (Self::SCALE[i], Self::SCALE[i + 1]);
+        let blend = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * fraction).round() as u8;
+        Color32::from_rgb(
+            blend(from.r(), to.r()),
+            blend(from.g(), to.g()),
+            blend(from.b(), to.b()),
+        )
+    }
+
+    /// Sets the range to cover the chosen curvature of given points
+    pub fn fit_range<'a, I>(&mut self, curvatures: I)
+    where
+        I: Iterator<Item = &'a Curvatures>,
+    {
+        let mut values: Vec<f32> = curvatures
+            .map(|curvatures| self.kind.value(curvatures))
+            .filter(|value| value.is_finite())
+            .collect();
+        if values.is_empty() {
+            return;
+        }
+        values.sort_by(f32::total_cmp);
+        let skipped = (values.len() as f32 * Self::OUTLIER_FRACTION) as usize;
+        self.min = values[skipped];
+        self.max = values[values.len() - 1 - skipped];
+    }
+}
+
+impl Default for CurvatureMap {
+    fn default() -> Self {
+        Self {
+            kind: CurvatureKind::Mean,
+            min: -0.01,
+            max: 0.01,
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use egui::Color32;
+use nalgebra::Vector3;
+
+/// False color visualisations that replace the lit color of the surface
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DebugView {
+    Off,
+    GeometricNormal,
+    MappedNormal,
+    TangentU,
+    TangentV,
+    Uv,
+    Depth,
+    Barycentric,
+    TriangleId,
+    DiffuseOnly,
+    SpecularOnly,
+    Curvature,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 12] = [
+        DebugView::Off,
+        DebugView::GeometricNormal,
+        DebugView::MappedNormal,
+        DebugView::TangentU,
+        DebugView::TangentV,
+        DebugView::Uv,
+        DebugView::Depth,
+        DebugView::Barycentric,
+        DebugView::TriangleId,
+        DebugView::DiffuseOnly,
+        DebugView::SpecularOnly,
+        DebugView::Curvature,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            DebugView::Off => "Off",
+            DebugView::GeometricNormal => "Geometric normal",
+            DebugView::MappedNormal => "Normal-mapped normal",
+            DebugView::TangentU => "Tangent pu",
+            DebugView::TangentV => "Tangent pv",
+            DebugView::Uv => "UV",
+            DebugView::Depth => "Depth",
+            DebugView::Barycentric => "Barycentric",
+            DebugView::TriangleId => "Triangle ID",
+            DebugView::DiffuseOnly => "Diffuse only",
+            DebugView::SpecularOnly => "Specular only",
+            DebugView::Curvature => "Curvature",
+        }
+    }
+
+    /// Maps each coordinate of unit vector from [-1, 1] to [0, 255]
+    pub fn vector_color(v: Vector3<f32>) -> Color32 {
+        let scale = |c: f32| ((c.clamp(-1.0, 1.0) * 0.5 + 0.5) * 255.0) as u8;
+        Color32::from_rgb(scale(v.x), scale(v.y), scale(v.z))
+    }
+
+    /// Maps each coordinate from [0, 1] to [0, 255]
+    pub fn unit_color(r: f32, g: f32, b: f32) -> Color32 {
+        let scale = |c: f32| (c.clamp(0.0, 1.0) * 255.0) as u8;
+        Color32::from_rgb(scale(r), scale(g), scale(b))
+    }
+
+    /// Assigns pseudo-random but stable color to each id
+    pub fn id_color(id: usize) -> Color32 {
+        let mut hash = (id as u32).wrapping_mul(0x9E37_79B9);
+        hash ^= hash >> 16;
+        hash = hash.wrapping_mul(0x85EB_CA6B);
+        hash ^= hash >> 13;
+        let [r, g, b, _] = hash.to_le_bytes();
+        Color32::from_rgb(r, g, b)
+    }
+}
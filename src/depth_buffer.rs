@@ -0,0 +1,131 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use egui::{Color32, ColorImage};
+
+use crate::mesh::Mesh;
+
+/// Stores the highest Z (closest to the camera) painted at each pixel, together with
+/// the color of that pixel, so the filled surface can be kept as an image and reused
+/// while nothing visible changes. Each pixel packs depth (upper 32 bits, encoded so
+/// integer order matches float order) and color (lower 32 bits) into one atomic,
+/// so it can be filled from many `PolygonFiller`s in parallel.
+pub struct DepthBuffer {
+    min_x: i32,
+    max_y: i32,
+    width: usize,
+    height: usize,
+    data: Vec<AtomicU64>,
+}
+
+impl DepthBuffer {
+    // Scanline filling can paint one pixel outside of the exact bounding box
+    const MARGIN: i32 = 2;
+    const EMPTY: u64 = 0;
+
+    pub fn new(meshes: &[Mesh]) -> Self {
+        let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+        let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for points in meshes.iter().map(|mesh| mesh.points()) {
+            for row in 0..points.rows() {
+                for col in 0..points.cols() {
+                    let p = points.at(row, col).after_rotation().p();
+                    min_x = min_x.min(p.x);
+                    min_y = min_y.min(p.y);
+                    max_x = max_x.max(p.x);
+                    max_y = max_y.max(p.y);
+                }
+            }
+        }
+        if !min_x.is_finite() || !min_y.is_finite() || !max_x.is_finite() || !max_y.is_finite() {
+            (min_x, min_y, max_x, max_y) = (0.0, 0.0, 0.0, 0.0);
+        }
+        let min_x = min_x.floor() as i32 - Self::MARGIN;
+        let min_y = min_y.floor() as i32 - Self::MARGIN;
+        let max_x = max_x.ceil() as i32 + Self::MARGIN;
+        let max_y = max_y.ceil() as i32 + Self::MARGIN;
+        let width = (max_x - min_x + 1) as usize;
+        let height = (max_y - min_y + 1) as usize;
+        let data = (0..width * height)
+            .map(|_| AtomicU64::new(Self::EMPTY))
+            .collect();
+        Self {
+            min_x,
+            max_y,
+            width,
+            height,
+            data,
+        }
+    }
+
+    /// Index of pixel, rows are stored from the top (highest y) like in images
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        let column = x - self.min_x;
+        let row = self.max_y - y;
+        if column < 0 || row < 0 || column as usize >= self.width || row as usize >= self.height {
+            return None;
+        }
+        Some(row as usize * self.width + column as usize)
+    }
+
+    /// Maps float to integer with the same ordering
+    fn depth_key(z: f32) -> u32 {
+        let bits = z.to_bits();
+        match bits & 0x8000_0000 != 0 {
+            true => !bits,
+            false => bits | 0x8000_0000,
+        }
+    }
+
+    fn depth_from_key(key: u32) -> f32 {
+        match key & 0x8000_0000 != 0 {
+            true => f32::from_bits(key & 0x7FFF_FFFF),
+            false => f32::from_bits(!key),
+        }
+    }
+
+    /// Paints pixel if it is closer to the camera than what is already there
+    pub fn paint(&self, x: i32, y: i32, z: f32, color: Color32) {
+        let Some(id) = self.index(x, y) else {
+            return;
+        };
+        let packed =
+            ((Self::depth_key(z) as u64) << 32) | u32::from_le_bytes(color.to_array()) as u64;
+        self.data[id].fetch_max(packed, Ordering::Relaxed);
+    }
+
+    /// Returns depth at given pixel or `None` if nothing was painted there
+    pub fn depth_at(&self, x: i32, y: i32) -> Option<f32> {
+        let id = self.index(x, y)?;
+        match self.data[id].load(Ordering::Relaxed) {
+            Self::EMPTY => None,
+            packed => Some(Self::depth_from_key((packed >> 32) as u32)),
+        }
+    }
+
+    /// Position of top left pixel in the same coordinates as painted points
+    pub fn top_left(&self) -> (i32, i32) {
+        (self.min_x, self.max_y)
+    }
+
+    pub fn size(&self) -> [usize; 2] {
+        [self.width, self.height]
+    }
+
+    pub fn to_color_image(&self) -> ColorImage {
+        let pixels = self
+            .data
+            .iter()
+            .map(|cell| match cell.load(Ordering::Relaxed) {
+                Self::EMPTY => Color32::TRANSPARENT,
+                packed => {
+                    let [r, g, b, a] = (packed as u32).to_le_bytes();
+                    Color32::from_rgba_premultiplied(r, g, b, a)
+                }
+            })
+            .collect();
+        ColorImage {
+            size: self.size(),
+            pixels,
+        }
+    }
+}
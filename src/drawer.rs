@@ -2,7 +2,10 @@ use egui::{Color32, Pos2};
 use nalgebra::Vector3;
 
 use crate::{
-    control_points::{ControlPoints, CONTROL_POINT_COLS, CONTROL_POINT_ROWS},
+    control_point_editor::{ControlPointEditor, ControlPointId},
+    control_points::ControlPoints,
+    curvature::CurvatureMap,
+    depth_buffer::DepthBuffer,
     light_source::LightSource,
     mesh::Mesh,
     point::Points2DArr,
@@ -11,12 +14,69 @@ use crate::{
     triangle_mesh_filler::ControlsState,
 };
 
+/// Which per-vertex vectors should be drawn on top of the surface and how
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct VectorGlyphs {
+    pub show_n: bool,
+    pub show_pu: bool,
+    pub show_pv: bool,
+    pub length: f32,
+    /// Glyphs are drawn only for every `step`-th row and column of points
+    pub step: usize,
+}
+
+impl VectorGlyphs {
+    pub const N_COLOR: Color32 = Color32::RED;
+    pub const PU_COLOR: Color32 = Color32::GREEN;
+    pub const PV_COLOR: Color32 = Color32::GOLD;
+
+    pub fn any_visible(&self) -> bool {
+        self.show_n || self.show_pu || self.show_pv
+    }
+}
+
+impl Default for VectorGlyphs {
+    fn default() -> Self {
+        Self {
+            show_n: false,
+            show_pu: false,
+            show_pv: false,
+            length: 20.0,
+            step: 1,
+        }
+    }
+}
+
+/// How triangle edges are drawn when mesh is shown
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Wireframe {
+    /// Draw only edges which are not covered by the filled surface
+    pub hidden_line: bool,
+    /// Skip diagonals and draw only lines of constant u or v
+    pub grid_only: bool,
+    pub color: Color32,
+    pub thickness: f32,
+}
+
+impl Default for Wireframe {
+    fn default() -> Self {
+        Self {
+            hidden_line: false,
+            grid_only: false,
+            color: Color32::BLUE,
+            thickness: 1.5,
+        }
+    }
+}
+
 pub struct Drawer<'ep> {
     screen_center: Pos2,
     painter: &'ep egui::Painter,
 }
 
 impl<'ep> Drawer<'ep> {
+    pub const DEGENERATE_POINT_COLOR: Color32 = Color32::from_rgb(230, 0, 230);
+
     pub fn new(screen_center: Pos2, painter: &'ep egui::Painter) -> Self {
         Self {
             screen_center,
@@ -39,60 +99,233 @@ impl<'ep> Drawer<'ep> {
     // We don't apply rotations to them, so we need to rotate them while drawing
     pub fn draw_control_points(
         &self,
+        patch: usize,
         control_points: &ControlPoints,
         controls_state: &ControlsState,
+        editor: &ControlPointEditor,
     ) {
         const WIDTH: f32 = 3.0;
-        let x_rotation = Rotations::create_x_rotation_matrix(controls_state.beta());
-        let z_rotation = Rotations::create_z_rotation_matrix(controls_state.alfa());
-        let rotation = z_rotation * x_rotation;
-        for i in 0..CONTROL_POINT_ROWS {
-            for j in 0..CONTROL_POINT_COLS {
+        const HIGHLIGHT_WIDTH: f32 = 5.0;
+        let rotation =
+            Rotations::create_rotation_matrix(controls_state.alfa(), controls_state.beta());
+        let id_at = |row: usize, col: usize| Some(ControlPointId { patch, row, col });
+        for i in 0..control_points.rows() {
+            for j in 0..control_points.cols() {
                 let control_point = rotation * control_points.at(i, j);
                 let position = self.point_to_screen(&control_point);
-                self.painter.circle(
-                    position,
-                    WIDTH,
-                    Color32::BLACK,
-                    egui::Stroke {
-                        color: Color32::BLACK,
-                        width: WIDTH,
-                    },
-                );
+                let (color, width) = if editor.selected() == id_at(i, j) {
+                    (Color32::RED, HIGHLIGHT_WIDTH)
+                } else if editor.hovered() == id_at(i, j) {
+                    (Color32::from_rgb(255, 140, 0), HIGHLIGHT_WIDTH)
+                } else {
+                    (Color32::BLACK, WIDTH)
+                };
+                self.painter
+                    .circle(position, width, color, egui::Stroke { color, width });
+                if editor.selected() == id_at(i, j) {
+                    self.painter.text(
+                        position + egui::vec2(2.0 * HIGHLIGHT_WIDTH, -2.0 * HIGHLIGHT_WIDTH),
+                        egui::Align2::LEFT_BOTTOM,
+                        format!("patch {} [{}, {}]", patch, i, j),
+                        egui::FontId::monospace(12.0),
+                        Color32::BLACK,
+                    );
+                }
             }
         }
     }
 
-    pub fn draw_mesh(&self, mesh: &Mesh) {
+    pub fn draw_degenerate_points(&self, mesh: &Mesh) {
+        const RADIUS: f32 = 4.0;
+        for pos in mesh.degenerate_points() {
+            let point = mesh.points().at_pos(*pos).after_rotation().p();
+            self.painter.circle_stroke(
+                self.point_to_screen(&point),
+                RADIUS,
+                egui::Stroke::new(2.0, Self::DEGENERATE_POINT_COLOR),
+            );
+        }
+    }
+
+    /// When `depth_buffer` is given, parts of edges hidden behind the surface are skipped
+    pub fn draw_mesh(
+        &self,
+        mesh: &Mesh,
+        wireframe: &Wireframe,
+        depth_buffer: Option<&DepthBuffer>,
+    ) {
         for triangle in mesh.triangles() {
-            self.draw_triangle(triangle, mesh.points());
+            self.draw_triangle(triangle, mesh.points(), wireframe, depth_buffer);
         }
     }
 
-    fn draw_triangle(&self, triangle: &Triangle, points: &Points2DArr) {
+    fn draw_triangle(
+        &self,
+        triangle: &Triangle,
+        points: &Points2DArr,
+        wireframe: &Wireframe,
+        depth_buffer: Option<&DepthBuffer>,
+    ) {
         let vertices = triangle.vertices();
+        let stroke = egui::Stroke {
+            width: wireframe.thickness,
+            color: wireframe.color,
+        };
         for id in 0..3 {
             let next_id = (id + 1) % 3;
             let start_ids = vertices[id];
             let end_ids = vertices[next_id];
-            let start = points.at(start_ids.row, start_ids.col);
-            let end = points.at(end_ids.row, end_ids.col);
-            let start_position = self.point_to_screen(&start.after_rotation().p());
-            let end_position = self.point_to_screen(&end.after_rotation().p());
+            let is_diagonal = start_ids.row != end_ids.row && start_ids.col != end_ids.col;
+            if wireframe.grid_only && is_diagonal {
+                continue;
+            }
+            let start = points.at(start_ids.row, start_ids.col).after_rotation().p();
+            let end = points.at(end_ids.row, end_ids.col).after_rotation().p();
+            match depth_buffer {
+                Some(depth_buffer) => self.draw_visible_parts(start, end, depth_buffer, stroke),
+                None => {
+                    self.painter.line_segment(
+                        [self.point_to_screen(&start), self.point_to_screen(&end)],
+                        stroke,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Samples the edge once per pixel and draws only runs of samples that are not behind
+    /// the depth stored in `depth_buffer`
+    fn draw_visible_parts(
+        &self,
+        start: Vector3<f32>,
+        end: Vector3<f32>,
+        depth_buffer: &DepthBuffer,
+        stroke: egui::Stroke,
+    ) {
+        const DEPTH_TOLERANCE: f32 = 2.0;
+        let diff = end - start;
+        let samples = diff.x.abs().max(diff.y.abs()).ceil().max(1.0) as usize;
+        let mut run_start: Option<Vector3<f32>> = None;
+        let mut previous = start;
+        for i in 0..=samples {
+            let sample = start + diff * (i as f32 / samples as f32);
+            let visible =
+                match depth_buffer.depth_at(sample.x.round() as i32, sample.y.round() as i32) {
+                    Some(depth) => sample.z >= depth - DEPTH_TOLERANCE,
+                    None => true,
+                };
+            match (visible, run_start) {
+                (true, None) => run_start = Some(sample),
+                (false, Some(run)) => {
+                    self.painter.line_segment(
+                        [self.point_to_screen(&run), self.point_to_screen(&previous)],
+                        stroke,
+                    );
+                    run_start = None;
+                }
+                _ => {}
+            }
+            previous = sample;
+        }
+        if let Some(run) = run_start {
             self.painter.line_segment(
-                [start_position, end_position],
-                egui::Stroke {
-                    width: 1.5,
-                    color: Color32::BLUE,
-                },
+                [self.point_to_screen(&run), self.point_to_screen(&end)],
+                stroke,
             );
         }
     }
 
-    pub fn paint_pixel(&self, position: Pos2, color: Color32) {
-        let pos = self.pos_to_screen(&position);
-        let rect = egui::Rect::from_min_size(pos, egui::Vec2::new(1.0, 1.0));
-        self.painter.rect_filled(rect, 0.0, color);
+    pub fn draw_vector_glyphs(&self, points: &Points2DArr, glyphs: &VectorGlyphs) {
+        const WIDTH: f32 = 1.5;
+        let step = glyphs.step.max(1);
+        for row in (0..points.rows()).step_by(step) {
+            for col in (0..points.cols()).step_by(step) {
+                let data = points.at(row, col).after_rotation();
+                let vectors = [
+                    (glyphs.show_n, data.n(), VectorGlyphs::N_COLOR),
+                    (glyphs.show_pu, data.pu(), VectorGlyphs::PU_COLOR),
+                    (glyphs.show_pv, data.pv(), VectorGlyphs::PV_COLOR),
+                ];
+                let start = self.point_to_screen(&data.p());
+                for (show, vector, color) in vectors {
+                    if !show {
+                        continue;
+                    }
+                    let end = self.point_to_screen(&(data.p() + vector * glyphs.length));
+                    self.painter
+                        .line_segment([start, end], egui::Stroke::new(WIDTH, color));
+                }
+            }
+        }
+    }
+
+    /// Draws texture created from `depth_buffer`, so its pixels land at the painted positions
+    pub fn draw_frame(&self, texture: &egui::TextureHandle, depth_buffer: &DepthBuffer) {
+        let (left, top) = depth_buffer.top_left();
+        let [width, height] = depth_buffer.size();
+        let min = self.pos_to_screen(&Pos2 {
+            x: left as f32,
+            y: top as f32,
+        });
+        let rect = egui::Rect::from_min_size(min, egui::Vec2::new(width as f32, height as f32));
+        let uv = egui::Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0));
+        self.painter.image(texture.id(), rect, uv, Color32::WHITE);
+    }
+
+    /// Draws color scale of `curvature_map` with its range in the top right corner of `viewport`
+    pub fn draw_curvature_legend(&self, viewport: egui::Rect, curvature_map: &CurvatureMap) {
+        const MARGIN: f32 = 16.0;
+        const BAR_SIZE: egui::Vec2 = egui::Vec2::new(20.0, 200.0);
+        const SLICES: usize = 64;
+        const TICKS: usize = 4;
+        let font = egui::FontId::monospace(12.0);
+        let text_color = self.painter.ctx().style().visuals.text_color();
+
+        let bar = egui::Rect::from_min_size(
+            Pos2::new(
+                viewport.right() - MARGIN - BAR_SIZE.x,
+                viewport.top() + 2.0 * MARGIN,
+            ),
+            BAR_SIZE,
+        );
+        // Maximum is at the top
+        let slice_height = bar.height() / SLICES as f32;
+        for i in 0..SLICES {
+            let top = bar.top() + i as f32 * slice_height;
+            let slice = egui::Rect::from_min_max(
+                Pos2::new(bar.left(), top),
+                Pos2::new(bar.right(), top + slice_height + 0.5),
+            );
+            let t = 1.0 - (i as f32 + 0.5) / SLICES as f32;
+            self.painter
+                .rect_filled(slice, 0.0, CurvatureMap::scale_color(t));
+        }
+        self.painter
+            .rect_stroke(bar, 0.0, egui::Stroke::new(1.0, text_color));
+
+        for i in 0..=TICKS {
+            let t = i as f32 / TICKS as f32;
+            let value = curvature_map.min + t * (curvature_map.max - curvature_map.min);
+            let y = bar.bottom() - t * bar.height();
+            self.painter.line_segment(
+                [Pos2::new(bar.left() - 4.0, y), Pos2::new(bar.left(), y)],
+                egui::Stroke::new(1.0, text_color),
+            );
+            self.painter.text(
+                Pos2::new(bar.left() - 6.0, y),
+                egui::Align2::RIGHT_CENTER,
+                format!("{:.3e}", value),
+                font.clone(),
+                text_color,
+            );
+        }
+        self.painter.text(
+            Pos2::new(bar.right(), bar.top() - 6.0),
+            egui::Align2::RIGHT_BOTTOM,
+            format!("{} curvature", curvature_map.kind.label()),
+            font,
+            text_color,
+        );
     }
 
     pub fn draw_light_source(&self, light_source: &LightSource) {
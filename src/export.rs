@@ -0,0 +1,80 @@
+use std::{ops::RangeInclusive, path::Path};
+
+use egui::Color32;
+
+use crate::{
+    mesh::Mesh,
+    point::{PData, Point},
+    stl::StlFormat,
+};
+
+/// Options shared by all mesh exporters
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ExportSettings {
+    /// Export points as they are seen on the screen instead of in their original position
+    pub rotated: bool,
+    pub stl_format: StlFormat,
+    /// Turn the surface into a closed solid by offsetting it against normals
+    pub thicken: bool,
+    pub thickness: f32,
+}
+
+impl ExportSettings {
+    pub const THICKNESS_RANGE: RangeInclusive<f32> = 0.1..=100.0;
+
+    pub fn point_data<'p>(&self, point: &'p Point) -> &'p PData {
+        match self.rotated {
+            true => point.after_rotation(),
+            false => point.before_rotation(),
+        }
+    }
+
+    /// Indices of triangle vertices in the points array, ordered counter-clockwise
+    /// when looking against the averaged normal of their vertices
+    pub fn oriented_triangles(&self, mesh: &Mesh) -> Vec<[usize; 3]> {
+        let points = mesh.points();
+        let data = |i: usize| self.point_data(&points.as_slice()[i]);
+        mesh.triangles()
+            .iter()
+            .map(|triangle| {
+                let vertices = triangle.vertices();
+                let [a, b, c] =
+                    [0, 1, 2].map(|i| points.get_index(vertices[i].row, vertices[i].col));
+                let normal = (data(b).p() - data(a).p()).cross(&(data(c).p() - data(a).p()));
+                match normal.dot(&(data(a).n() + data(b).n() + data(c).n())) < 0.0 {
+                    true => [a, c, b],
+                    false => [a, b, c],
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            rotated: false,
+            stl_format: StlFormat::Binary,
+            thicken: false,
+            thickness: 5.0,
+        }
+    }
+}
+
+/// Appearance of the surface written alongside exported geometry
+pub struct Material<'a> {
+    pub color: Color32,
+    pub kd: f32,
+    pub ks: f32,
+    pub m: u8,
+    pub texture_path: Option<&'a Path>,
+    pub normal_map_path: Option<&'a Path>,
+}
+
+impl Material<'_> {
+    /// Color channels in [0, 1]
+    pub fn color_rgb(&self) -> [f32; 3] {
+        let [r, g, b, _] = self.color.to_array();
+        [r, g, b].map(|c| c as f32 / u8::MAX as f32)
+    }
+}
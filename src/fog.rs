@@ -0,0 +1,86 @@
+use egui::Color32;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FogMode {
+    Linear,
+    Exponential,
+    ExponentialSquared,
+}
+
+impl FogMode {
+    pub const ALL: [FogMode; 3] = [
+        FogMode::Linear,
+        FogMode::Exponential,
+        FogMode::ExponentialSquared,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            FogMode::Linear => "Linear",
+            FogMode::Exponential => "Exponential",
+            FogMode::ExponentialSquared => "Exponential squared",
+        }
+    }
+}
+
+/// Depth cueing based on the rotated Z coordinate. The camera looks down the Z-axis,
+/// so points with lower Z are further away and get blended more toward the fog color.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Fog {
+    pub enabled: bool,
+    pub mode: FogMode,
+    pub color: Color32,
+    /// Z above which there is no fog
+    pub start_z: f32,
+    /// Z below which linear fog fully covers the surface
+    pub end_z: f32,
+    /// Density used by exponential modes
+    pub density: f32,
+}
+
+impl Fog {
+    pub fn apply(&self, color: Color32, z: f32) -> Color32 {
+        if !self.enabled {
+            return color;
+        }
+        let visibility = self.visibility(z);
+        let blend = |c: u8, f: u8| -> u8 {
+            (c as f32 * visibility + f as f32 * (1.0 - visibility)).round() as u8
+        };
+        Color32::from_rgb(
+            blend(color.r(), self.color.r()),
+            blend(color.g(), self.color.g()),
+            blend(color.b(), self.color.b()),
+        )
+    }
+
+    /// Returns value in [0, 1] where 1 means the point is not affected by fog at all
+    fn visibility(&self, z: f32) -> f32 {
+        let distance = (self.start_z - z).max(0.0);
+        let visibility = match self.mode {
+            FogMode::Linear => {
+                let range = self.start_z - self.end_z;
+                if range <= 0.0 {
+                    return if z >= self.start_z { 1.0 } else { 0.0 };
+                }
+                1.0 - distance / range
+            }
+            FogMode::Exponential => (-self.density * distance).exp(),
+            FogMode::ExponentialSquared => (-(self.density * distance).powi(2)).exp(),
+        };
+        visibility.clamp(0.0, 1.0)
+    }
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mode: FogMode::Linear,
+            color: Color32::WHITE,
+            start_z: 100.0,
+            end_z: -300.0,
+            density: 0.005,
+        }
+    }
+}
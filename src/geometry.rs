@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use nalgebra::Vector3;
+
+/// Computations on sets of points shared by meshes, patches and exporters
+pub struct Geometry;
+
+impl Geometry {
+    /// Splits `positions` into groups of points closer than `tolerance` to the first point
+    /// of their group. Every index belongs to exactly one group, in order of first points.
+    pub fn cluster_by_distance(positions: &[Vector3<f32>], tolerance: f32) -> Vec<Vec<usize>> {
+        // Spatial hash with cells of size `tolerance`, so only neighbouring cells must be checked
+        let cell_size = tolerance.max(f32::EPSILON);
+        let cell_of = |p: Vector3<f32>| {
+            (
+                (p.x / cell_size).floor() as i64,
+                (p.y / cell_size).floor() as i64,
+                (p.z / cell_size).floor() as i64,
+            )
+        };
+        let mut cells: HashMap<(i64, i64, i64), Vec<usize>> = HashMap::new();
+        for (id, &p) in positions.iter().enumerate() {
+            cells.entry(cell_of(p)).or_default().push(id);
+        }
+
+        let mut assigned = vec![false; positions.len()];
+        let mut clusters = vec![];
+        for id in 0..positions.len() {
+            if assigned[id] {
+                continue;
+            }
+            let p = positions[id];
+            let (cx, cy, cz) = cell_of(p);
+            let mut cluster = vec![];
+            for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let Some(candidates) = cells.get(&(cx + dx, cy + dy, cz + dz)) else {
+                            continue;
+                        };
+                        for &other in candidates {
+                            if !assigned[other] && (positions[other] - p).norm() <= tolerance {
+                                assigned[other] = true;
+                                cluster.push(other);
+                            }
+                        }
+                    }
+                }
+            }
+            clusters.push(cluster);
+        }
+        clusters
+    }
+
+    /// Moves center of bounding box of `points` to the origin and scales them uniformly,
+    /// so the longest side of the bounding box equals `size`
+    pub fn fit_to_size<'a, I>(points: I, size: f32)
+    where
+        I: Iterator<Item = &'a mut Vector3<f32>>,
+    {
+        let mut points: Vec<&mut Vector3<f32>> = points.collect();
+        let mut min = Vector3::repeat(f32::INFINITY);
+        let mut max = Vector3::repeat(f32::NEG_INFINITY);
+        for point in points.iter() {
+            min = min.inf(point);
+            max = max.sup(point);
+        }
+        let extent = (max - min).max();
+        if !extent.is_finite() || extent <= f32::EPSILON {
+            return;
+        }
+        let center = (min + max) / 2.0;
+        let scale = size / extent;
+        for point in points.iter_mut() {
+            **point = (**point - center) * scale;
+        }
+    }
+
+    /// Unit average of `normals`, each flipped to agree with the first one, so normals
+    /// of surfaces with opposite orientation don't cancel out
+    pub fn aligned_average_normal(normals: &[Vector3<f32>]) -> Vector3<f32> {
+        let reference = normals[0];
+        normals
+            .iter()
+            .map(|n| n * n.dot(&reference).signum())
+            .sum::<Vector3<f32>>()
+            .try_normalize(f32::EPSILON)
+            .unwrap_or(reference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_points_are_clustered() {
+        let positions = [
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(5.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.5, 0.0),
+            Vector3::new(5.0, 0.0, 0.9),
+            Vector3::new(0.0, 0.0, 0.0),
+        ];
+        let clusters = Geometry::cluster_by_distance(&positions, 1.0);
+        assert_eq!(clusters, vec![vec![0, 2, 4], vec![1, 3]]);
+    }
+
+    #[test]
+    fn fitted_points_are_centered_and_scaled() {
+        let mut points = [
+            Vector3::new(10.0, 20.0, 30.0),
+            Vector3::new(14.0, 21.0, 30.0),
+            Vector3::new(12.0, 22.0, 31.0),
+        ];
+        Geometry::fit_to_size(points.iter_mut(), 100.0);
+        assert_eq!(points[0], Vector3::new(-50.0, -25.0, -12.5));
+        assert_eq!(points[1], Vector3::new(50.0, 0.0, -12.5));
+        assert_eq!(points[2], Vector3::new(0.0, 25.0, 12.5));
+    }
+
+    #[test]
+    fn opposite_normals_do_not_cancel_out() {
+        let normals = [Vector3::z(), -Vector3::z(), Vector3::new(0.0, 0.6, -0.8)];
+        let n = Geometry::aligned_average_normal(&normals);
+        assert!((n.norm() - 1.0).abs() < 1e-6);
+        assert!(n.z > 0.9);
+    }
+}
@@ -0,0 +1,384 @@
+use std::{fs, path::Path};
+
+use anyhow::{Context, Error, Result};
+use nalgebra::Vector3;
+
+use crate::{
+    export::{ExportSettings, Material},
+    mesh::Mesh,
+};
+
+/// glTF 2.0 format: JSON description with geometry stored in a separate binary file
+pub struct Gltf;
+
+/// Part of the binary buffer with data of a single accessor
+struct View {
+    offset: usize,
+    length: usize,
+    target: u32,
+    count: usize,
+    component_type: u32,
+    accessor_type: &'static str,
+    /// `min` and `max` of positions, required by the specification
+    bounds: Option<(Vector3<f32>, Vector3<f32>)>,
+}
+
+impl Gltf {
+    const ARRAY_BUFFER: u32 = 34962;
+    const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+    const FLOAT: u32 = 5126;
+    const UNSIGNED_INT: u32 = 5125;
+
+    /// Writes all meshes as primitives of a single glTF mesh. Textures are copied next to
+    /// the `.gltf` file, so the exported asset doesn't depend on where they were loaded from.
+    /// Meshes without triangles are skipped, since glTF doesn't allow empty accessors.
+    pub fn export<P: AsRef<Path>>(
+        path: P,
+        meshes: &[Mesh],
+        material: &Material,
+        settings: &ExportSettings,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let stem = path
+            .file_stem()
+            .ok_or_else(|| Error::msg("Missing file name"))?
+            .to_string_lossy();
+        let bin_name = format!("{}.bin", stem);
+
+        let mut buffer: Vec<u8> = vec![];
+        let mut views = vec![];
+        let mut primitives = vec![];
+        for mesh in meshes.iter().filter(|mesh| !mesh.triangles().is_empty()) {
+            let first_view = views.len();
+            Self::append_mesh(&mut buffer, &mut views, mesh, settings);
+            primitives.push(format!(
+                r#"{{"attributes":{{"POSITION":{},"NORMAL":{},"TANGENT":{},"TEXCOORD_0":{}}},"indices":{},"material":0}}"#,
+                first_view,
+                first_view + 1,
+                first_view + 2,
+                first_view + 3,
+                first_view + 4
+            ));
+        }
+
+        if primitives.is_empty() {
+            return Err(Error::msg("There are no triangles to export"));
+        }
+
+        let mut images = vec![];
+        for (source, suffix) in [
+            (material.texture_path, "base_color"),
+            (material.normal_map_path, "normal"),
+        ] {
+            let Some(source) = source else {
+                images.push(None);
+                continue;
+            };
+            let extension = source
+                .extension()
+                .map(|extension| extension.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let name = format!("{}_{}.{}", stem, suffix, extension);
+            let destination = directory.join(&name);
+            if fs::canonicalize(source).ok() != fs::canonicalize(&destination).ok() {
+                fs::copy(source, &destination)
+                    .with_context(|| format!("Cannot copy {}", source.display()))?;
+            }
+            images.push(Some(name));
+        }
+
+        let json = Self::json(
+            &bin_name,
+            buffer.len(),
+            &views,
+            &primitives,
+            material,
+            &images,
+        );
+        fs::write(directory.join(&bin_name), &buffer).context("Cannot write glTF buffer")?;
+        fs::write(path, json).context("Cannot write glTF file")?;
+        Ok(())
+    }
+
+    fn append_mesh(
+        buffer: &mut Vec<u8>,
+        views: &mut Vec<View>,
+        mesh: &Mesh,
+        settings: &ExportSettings,
+    ) {
+        let points = mesh.points().as_slice();
+        let data = |i: usize| settings.point_data(&points[i]);
+
+        let mut min = Vector3::repeat(f32::INFINITY);
+        let mut max = Vector3::repeat(f32::NEG_INFINITY);
+        for i in 0..points.len() {
+            let p = data(i).p().map(Self::finite);
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        Self::append_view(buffer, views, points.len(), "VEC3", Some((min, max)), |i| {
+            data(i).p().iter().copied().collect()
+        });
+        Self::append_view(buffer, views, points.len(), "VEC3", None, |i| {
+            data(i).n().iter().copied().collect()
+        });
+        // Texture coordinate `s` follows `v`, so tangent is `pv` and bitangent is `pu`
+        Self::append_view(buffer, views, points.len(), "VEC4", None, |i| {
+            let (n, pu, pv) = (data(i).n(), data(i).pu(), data(i).pv());
+            let handedness = match n.cross(&pv).dot(&pu) < 0.0 {
+                true => -1.0,
+                false => 1.0,
+            };
+            vec![pv.x, pv.y, pv.z, handedness]
+        });
+        Self::append_view(buffer, views, points.len(), "VEC2", None, |i| {
+            vec![points[i].v(), points[i].u()]
+        });
+
+        let indices: Vec<u32> = settings
+            .oriented_triangles(mesh)
+            .into_iter()
+            .flatten()
+            .map(|index| index as u32)
+            .collect();
+        let offset = buffer.len();
+        for index in indices.iter() {
+            buffer.extend_from_slice(&index.to_le_bytes());
+        }
+        views.push(View {
+            offset,
+            length: buffer.len() - offset,
+            target: Self::ELEMENT_ARRAY_BUFFER,
+            count: indices.len(),
+            component_type: Self::UNSIGNED_INT,
+            accessor_type: "SCALAR",
+            bounds: None,
+        });
+    }
+
+    fn append_view<F>(
+        buffer: &mut Vec<u8>,
+        views: &mut Vec<View>,
+        count: usize,
+        accessor_type: &'static str,
+        bounds: Option<(Vector3<f32>, Vector3<f32>)>,
+        components: F,
+    ) where
+        F: Fn(usize) -> Vec<f32>,
+    {
+        let offset = buffer.len();
+        for i in 0..count {
+            for component in components(i) {
+                buffer.extend_from_slice(&Self::finite(component).to_le_bytes());
+            }
+        }
+        views.push(View {
+            offset,
+            length: buffer.len() - offset,
+            target: Self::ARRAY_BUFFER,
+            count,
+            component_type: Self::FLOAT,
+            accessor_type,
+            bounds,
+        });
+    }
+
+    /// Metallic-roughness material approximating Phong model: diffuse coefficient scales
+    /// base color, specular exponent is converted to roughness and specular coefficient
+    /// is stored using `KHR_materials_specular` extension
+    fn material_json(material: &Material, images: &[Option<String>]) -> String {
+        let [r, g, b] = match images[0].is_some() {
+            // Texture replaces the surface color
+            true => [material.kd; 3],
+            false => material.color_rgb().map(|c| c * material.kd),
+        };
+        // Blinn-Phong exponent to GGX roughness, alpha = sqrt(2 / (m + 2)), roughness = sqrt(alpha)
+        let roughness = (2.0 / (material.m as f32 + 2.0)).sqrt().sqrt();
+        let mut pbr = format!(
+            r#""baseColorFactor":[{},{},{},1],"metallicFactor":0,"roughnessFactor":{}"#,
+            Self::finite(r),
+            Self::finite(g),
+            Self::finite(b),
+            Self::finite(roughness)
+        );
+        let mut texture_id = 0;
+        if images[0].is_some() {
+            pbr.push_str(&format!(
+                r#","baseColorTexture":{{"index":{}}}"#,
+                texture_id
+            ));
+            texture_id += 1;
+        }
+        let mut json = format!(
+            r#"{{"name":"surface","pbrMetallicRoughness":{{{}}},"doubleSided":true"#,
+            pbr
+        );
+        if images[1].is_some() {
+            json.push_str(&format!(r#","normalTexture":{{"index":{}}}"#, texture_id));
+        }
+        json.push_str(&format!(
+            r#","extensions":{{"KHR_materials_specular":{{"specularFactor":{}}}}}}}"#,
+            Self::finite(material.ks)
+        ));
+        json
+    }
+
+    fn json(
+        bin_name: &str,
+        buffer_length: usize,
+        views: &[View],
+        primitives: &[String],
+        material: &Material,
+        images: &[Option<String>],
+    ) -> String {
+        let buffer_views: Vec<String> = views
+            .iter()
+            .map(|view| {
+                format!(
+                    r#"{{"buffer":0,"byteOffset":{},"byteLength":{},"target":{}}}"#,
+                    view.offset, view.length, view.target
+                )
+            })
+            .collect();
+        let accessors: Vec<String> = views
+            .iter()
+            .enumerate()
+            .map(|(i, view)| {
+                let bounds = match view.bounds {
+                    Some((min, max)) => format!(
+                        r#","min":[{},{},{}],"max":[{},{},{}]"#,
+                        min.x, min.y, min.z, max.x, max.y, max.z
+                    ),
+                    None => String::new(),
+                };
+                format!(
+                    r#"{{"bufferView":{},"componentType":{},"count":{},"type":"{}"{}}}"#,
+                    i, view.component_type, view.count, view.accessor_type, bounds
+                )
+            })
+            .collect();
+        let image_names: Vec<&String> = images.iter().flatten().collect();
+        let textures_json = match image_names.is_empty() {
+            true => String::new(),
+            false => {
+                let images_json: Vec<String> = image_names
+                    .iter()
+                    .map(|name| format!(r#"{{"uri":"{}"}}"#, Self::uri(name)))
+                    .collect();
+                let textures: Vec<String> = (0..image_names.len())
+                    .map(|i| format!(r#"{{"sampler":0,"source":{}}}"#, i))
+                    .collect();
+                format!(
+                    r#","samplers":[{{}}],"images":[{}],"textures":[{}]"#,
+                    images_json.join(","),
+                    textures.join(",")
+                )
+            }
+        };
+        format!(
+            concat!(
+                r#"{{"asset":{{"version":"2.0","generator":"Triangle Mesh Filler"}},"#,
+                r#""extensionsUsed":["KHR_materials_specular"],"#,
+                r#""scene":0,"scenes":[{{"nodes":[0]}}],"nodes":[{{"mesh":0,"name":"surface"}}],"#,
+                r#""meshes":[{{"primitives":[{}]}}],"materials":[{}]{},"#,
+                r#""buffers":[{{"uri":"{}","byteLength":{}}}],"#,
+                r#""bufferViews":[{}],"accessors":[{}]}}"#,
+                "\n"
+            ),
+            primitives.join(","),
+            Self::material_json(material, images),
+            textures_json,
+            Self::uri(bin_name),
+            buffer_length,
+            buffer_views.join(","),
+            accessors.join(",")
+        )
+    }
+
+    /// JSON has no representation of NaN and infinities, and viewers reject them in buffers,
+    /// so they are written as zero (e.g. positions at degenerate points)
+    fn finite(value: f32) -> f32 {
+        match value.is_finite() {
+            true => value,
+            false => 0.0,
+        }
+    }
+
+    /// Relative URI of a file in the same directory, escaped for use inside JSON string
+    fn uri(name: &str) -> String {
+        name.replace('%', "%25")
+            .replace(' ', "%20")
+            .replace('\\', "\\\\")
+            .replace('"', "%22")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::Color32;
+    use serde_json::Value;
+
+    use super::*;
+    use crate::{
+        mesh::fixtures::{move_point, square, temp_path},
+        point::{Points2DArr, PosIn2DArr},
+    };
+
+    #[test]
+    fn exported_accessors_match_buffer() {
+        let path = temp_path("export.gltf");
+        let bin_path = path.with_extension("bin");
+        let empty = Mesh::new(vec![], Points2DArr::from_data(vec![], 0, 0));
+        let material = Material {
+            color: Color32::LIGHT_GREEN,
+            kd: 0.5,
+            ks: f32::NAN,
+            m: 20,
+            texture_path: None,
+            normal_map_path: None,
+        };
+        // Point which couldn't be evaluated
+        let mut with_nan = square(0.0);
+        move_point(
+            &mut with_nan,
+            PosIn2DArr { row: 1, col: 1 },
+            Vector3::repeat(f32::NAN),
+        );
+        let meshes = [with_nan, empty, square(1.0)];
+        Gltf::export(&path, &meshes, &material, &ExportSettings::default()).unwrap();
+        let json: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let buffer_length = fs::metadata(&bin_path).unwrap().len();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&bin_path).unwrap();
+
+        assert_eq!(json["buffers"][0]["byteLength"], buffer_length);
+        assert_eq!(json["meshes"][0]["primitives"].as_array().unwrap().len(), 2);
+        let views = json["bufferViews"].as_array().unwrap();
+        let accessors = json["accessors"].as_array().unwrap();
+        assert_eq!(accessors.len(), 2 * 5);
+        for accessor in accessors {
+            let view = &views[accessor["bufferView"].as_u64().unwrap() as usize];
+            let components = match accessor["type"].as_str().unwrap() {
+                "SCALAR" => 1,
+                "VEC2" => 2,
+                "VEC3" => 3,
+                "VEC4" => 4,
+                other => panic!("unexpected accessor type {}", other),
+            };
+            let count = accessor["count"].as_u64().unwrap();
+            let length = view["byteLength"].as_u64().unwrap();
+            assert!(count > 0);
+            assert_eq!(length, count * components * 4);
+            assert!(view["byteOffset"].as_u64().unwrap() + length <= buffer_length);
+            for bound in ["min", "max"] {
+                if let Some(values) = accessor[bound].as_array() {
+                    assert!(values
+                        .iter()
+                        .all(|value| value.as_f64().unwrap().is_finite()));
+                }
+            }
+        }
+        assert!(accessors[0]["min"].is_array());
+    }
+}
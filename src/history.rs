@@ -0,0 +1,246 @@
+use std::path::PathBuf;
+
+use egui::Color32;
+
+use crate::{
+    control_points::ControlPoints, light_source::LightSource, triangle_mesh_filler::ControlsState,
+};
+
+/// Light source parameters set by the user. Position on the animation path is not included,
+/// so running animation doesn't create new history entries.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LightSettings {
+    pub color: Color32,
+    pub radius_base: f32,
+    pub z: f32,
+}
+
+impl LightSettings {
+    pub fn from_light_source(light_source: &LightSource) -> Self {
+        Self {
+            color: light_source.color(),
+            radius_base: light_source.radius_base(),
+            z: light_source.position().z,
+        }
+    }
+
+    pub fn apply_to(&self, light_source: &mut LightSource) {
+        *light_source.color_mut() = self.color;
+        *light_source.radius_base_mut() = self.radius_base;
+        light_source.position_mut().z = self.z;
+    }
+}
+
+/// Everything that can be restored by undo and redo
+#[derive(Clone, PartialEq)]
+pub struct Snapshot {
+    pub patches: Vec<ControlPoints>,
+    /// Settings of the surface and its material, with overlays, animation and debug view reset
+    pub controls_state: ControlsState,
+    pub light: LightSettings,
+    pub texture_path: Option<PathBuf>,
+    pub normal_map_path: Option<PathBuf>,
+}
+
+impl Snapshot {
+    /// Short description of what differs between `previous` and this snapshot
+    fn describe_changes(&self, previous: &Snapshot) -> String {
+        let mut changes = vec![];
+        if self.patches != previous.patches {
+            changes.push("Edit control points");
+        }
+        if self.controls_state != previous.controls_state {
+            changes.push("Change settings");
+        }
+        if self.light != previous.light {
+            changes.push("Change light");
+        }
+        if self.texture_path != previous.texture_path {
+            changes.push(match self.texture_path {
+                Some(_) => "Load texture",
+                None => "Remove texture",
+            });
+        }
+        if self.normal_map_path != previous.normal_map_path {
+            changes.push(match self.normal_map_path {
+                Some(_) => "Load normal map",
+                None => "Remove normal map",
+            });
+        }
+        changes.join(", ")
+    }
+}
+
+struct HistoryEntry {
+    label: String,
+    snapshot: Snapshot,
+}
+
+/// Linear undo/redo stack of snapshots. Recording a new snapshot after undo discards
+/// all entries that could be redone.
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    current: usize,
+}
+
+impl History {
+    const MAX_ENTRIES: usize = 100;
+
+    pub fn new(initial: Snapshot) -> Self {
+        Self {
+            entries: vec![HistoryEntry {
+                label: "Load file".to_string(),
+                snapshot: initial,
+            }],
+            current: 0,
+        }
+    }
+
+    /// Adds `snapshot` as a new entry, unless it is the same as the current one.
+    /// While the pointer is held (e.g. dragging a slider or a control point) nothing is
+    /// recorded, so the whole drag becomes a single entry once the pointer is released.
+    pub fn record(&mut self, snapshot: Snapshot, pointer_down: bool) {
+        if pointer_down {
+            return;
+        }
+        let current = &self.entries[self.current].snapshot;
+        if snapshot == *current {
+            return;
+        }
+        let label = snapshot.describe_changes(current);
+        self.entries.truncate(self.current + 1);
+        self.entries.push(HistoryEntry { label, snapshot });
+        if self.entries.len() > Self::MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.current = self.entries.len() - 1;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.current > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.current + 1 < self.entries.len()
+    }
+
+    pub fn undo(&mut self) -> Option<&Snapshot> {
+        match self.can_undo() {
+            true => self.jump_to(self.current - 1),
+            false => None,
+        }
+    }
+
+    pub fn redo(&mut self) -> Option<&Snapshot> {
+        match self.can_redo() {
+            true => self.jump_to(self.current + 1),
+            false => None,
+        }
+    }
+
+    pub fn jump_to(&mut self, index: usize) -> Option<&Snapshot> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        self.current = index;
+        Some(&self.entries[index].snapshot)
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|entry| entry.label.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    fn snapshot(z: f32) -> Snapshot {
+        let points = vec![Vector3::zeros(); 4];
+        Snapshot {
+            patches: vec![ControlPoints::new(points, 2, 2).unwrap()],
+            controls_state: ControlsState::default(),
+            light: LightSettings {
+                color: Color32::WHITE,
+                radius_base: 100.0,
+                z,
+            },
+            texture_path: None,
+            normal_map_path: None,
+        }
+    }
+
+    fn light_z(snapshot: Option<&Snapshot>) -> Option<f32> {
+        snapshot.map(|snapshot| snapshot.light.z)
+    }
+
+    #[test]
+    fn push_undo_and_redo() {
+        let mut history = History::new(snapshot(0.0));
+        history.record(snapshot(1.0), false);
+        history.record(snapshot(2.0), false);
+        assert_eq!(history.current_index(), 2);
+        assert_eq!(history.labels().nth(1), Some("Change light"));
+
+        assert_eq!(light_z(history.undo()), Some(1.0));
+        assert_eq!(light_z(history.undo()), Some(0.0));
+        assert!(!history.can_undo());
+        assert_eq!(light_z(history.undo()), None);
+
+        assert_eq!(light_z(history.redo()), Some(1.0));
+        assert_eq!(light_z(history.redo()), Some(2.0));
+        assert!(!history.can_redo());
+        assert_eq!(light_z(history.redo()), None);
+    }
+
+    #[test]
+    fn unchanged_snapshot_is_not_recorded() {
+        let mut history = History::new(snapshot(0.0));
+        history.record(snapshot(0.0), false);
+        assert_eq!(history.labels().count(), 1);
+    }
+
+    #[test]
+    fn push_after_undo_drops_redo_entries() {
+        let mut history = History::new(snapshot(0.0));
+        history.record(snapshot(1.0), false);
+        history.record(snapshot(2.0), false);
+        history.undo();
+        history.undo();
+        history.record(snapshot(3.0), false);
+
+        assert_eq!(history.labels().count(), 2);
+        assert!(!history.can_redo());
+        assert_eq!(light_z(history.undo()), Some(0.0));
+        assert_eq!(light_z(history.redo()), Some(3.0));
+    }
+
+    #[test]
+    fn changes_are_coalesced_while_pointer_is_held() {
+        let mut history = History::new(snapshot(0.0));
+        for z in 1..=10 {
+            history.record(snapshot(z as f32), true);
+        }
+        assert_eq!(history.labels().count(), 1);
+
+        history.record(snapshot(10.0), false);
+        assert_eq!(history.labels().count(), 2);
+        assert_eq!(light_z(history.undo()), Some(0.0));
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped_over_limit() {
+        let mut history = History::new(snapshot(0.0));
+        for z in 1..=History::MAX_ENTRIES + 5 {
+            history.record(snapshot(z as f32), false);
+        }
+        assert_eq!(history.labels().count(), History::MAX_ENTRIES);
+        assert_eq!(light_z(history.jump_to(0)), Some(6.0));
+    }
+}
@@ -1,6 +1,7 @@
 use egui::Color32;
 use nalgebra::Vector3;
 
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct LightSource {
     position: Vector3<f32>,
     color: Color32,
@@ -32,6 +33,10 @@ impl LightSource {
         &mut self.color
     }
 
+    pub fn radius_base(&self) -> f32 {
+        self.radius_base
+    }
+
     pub fn radius_base_mut(&mut self) -> &mut f32 {
         &mut self.radius_base
     }
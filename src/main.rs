@@ -1,27 +1,157 @@
-use std::env;
+use std::{env, str::FromStr};
 
 use anyhow::{Error, Result};
-use triangle_mesh_filler::TriangleMeshFiller;
+use export::ExportSettings;
+use stl::StlFormat;
+use triangle_mesh_filler::{ControlsState, TriangleMeshFiller};
 
+mod adaptive_triangulation;
+mod basis;
 mod colors_manager;
+mod continuity;
+mod control_point_editor;
+mod control_point_inspector;
 mod control_points;
+mod curvature;
+mod debug_view;
+mod depth_buffer;
 mod drawer;
+mod export;
+mod fog;
+mod geometry;
+mod gltf;
+mod history;
 mod light_source;
 mod mesh;
+mod obj;
 mod point;
 mod polygon_filler;
 mod rotations;
+mod shading;
+mod stl;
 mod texture_loader;
 mod triangle;
 mod triangle_mesh_filler;
 
+/// Command line arguments:
+/// `[CONFIG] [--export-stl OUTPUT [--ascii] [--thickness T] [--accuracy N | --accuracy-u N --accuracy-v N]]`.
+/// With `--export-stl` the surface is exported without opening the window.
+#[derive(Debug, PartialEq)]
+struct Arguments {
+    config_path: String,
+    stl_path: Option<String>,
+    stl_format: StlFormat,
+    thickness: Option<f32>,
+    accuracy_u: Option<usize>,
+    accuracy_v: Option<usize>,
+}
+
+impl Arguments {
+    const DEFAULT_CONFIG_PATH: &'static str = "config/default_config.txt";
+
+    fn parse(args: &[String]) -> Result<Self> {
+        let mut config_path = None;
+        let mut stl_path = None;
+        let mut stl_format = StlFormat::Binary;
+        let mut thickness = None;
+        let mut accuracy_u = None;
+        let mut accuracy_v = None;
+        // Options which make sense only when exporting
+        let mut export_option = None;
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--export-stl" => {
+                    let path = args
+                        .next()
+                        .ok_or_else(|| Error::msg("Missing output path after --export-stl"))?;
+                    stl_path = Some(path.clone());
+                }
+                "--ascii" => {
+                    stl_format = StlFormat::Ascii;
+                    export_option = Some(arg);
+                }
+                "--thickness" => {
+                    let value: f32 = Self::value(&mut args, arg)?;
+                    if !ExportSettings::THICKNESS_RANGE.contains(&value) {
+                        return Err(Error::msg(format!(
+                            "Thickness must be between {} and {}, got {}",
+                            ExportSettings::THICKNESS_RANGE.start(),
+                            ExportSettings::THICKNESS_RANGE.end(),
+                            value
+                        )));
+                    }
+                    thickness = Some(value);
+                    export_option = Some(arg);
+                }
+                "--accuracy" | "--accuracy-u" | "--accuracy-v" => {
+                    let value: usize = Self::value(&mut args, arg)?;
+                    if !(ControlsState::MIN_ACCURACY..=ControlsState::MAX_ACCURACY).contains(&value)
+                    {
+                        return Err(Error::msg(format!(
+                            "Accuracy must be between {} and {}, got {}",
+                            ControlsState::MIN_ACCURACY,
+                            ControlsState::MAX_ACCURACY,
+                            value
+                        )));
+                    }
+                    if arg != "--accuracy-v" {
+                        accuracy_u = Some(value);
+                    }
+                    if arg != "--accuracy-u" {
+                        accuracy_v = Some(value);
+                    }
+                    export_option = Some(arg);
+                }
+                _ if arg.starts_with("--") => {
+                    return Err(Error::msg(format!("Unknown option: {}", arg)));
+                }
+                _ if config_path.is_none() => config_path = Some(arg.clone()),
+                _ => return Err(Error::msg(format!("Unexpected argument: {}", arg))),
+            }
+        }
+        if let (None, Some(option)) = (&stl_path, export_option) {
+            return Err(Error::msg(format!("{} requires --export-stl", option)));
+        }
+        Ok(Self {
+            config_path: config_path.unwrap_or_else(|| Self::DEFAULT_CONFIG_PATH.to_string()),
+            stl_path,
+            stl_format,
+            thickness,
+            accuracy_u,
+            accuracy_v,
+        })
+    }
+
+    /// Parses the value following `option`
+    fn value<'a, T, I>(args: &mut I, option: &str) -> Result<T>
+    where
+        T: FromStr,
+        I: Iterator<Item = &'a String>,
+    {
+        let value = args
+            .next()
+            .ok_or_else(|| Error::msg(format!("Missing value after {}", option)))?;
+        value
+            .parse()
+            .map_err(|_| Error::msg(format!("Invalid value of {}: {}", option, value)))
+    }
+}
+
 fn main() -> Result<()> {
-    let args: Vec<_> = env::args().collect();
-    let config_path = args
-        .get(1)
-        .map(|s| s.as_str())
-        .unwrap_or("config/default_config.txt");
-    let app = TriangleMeshFiller::load_from_file(config_path)?;
+    let args: Vec<_> = env::args().skip(1).collect();
+    let arguments = Arguments::parse(&args)?;
+    let mut app = TriangleMeshFiller::load_from_file(&arguments.config_path)?;
+    if let Some(stl_path) = arguments.stl_path {
+        app.set_triangulation_accuracy(arguments.accuracy_u, arguments.accuracy_v);
+        let settings = app.export_settings_mut();
+        settings.stl_format = arguments.stl_format;
+        if let Some(thickness) = arguments.thickness {
+            settings.thicken = true;
+            settings.thickness = thickness;
+        }
+        return app.export_stl(stl_path);
+    }
     let native_options = eframe::NativeOptions {
         viewport: egui::ViewportBuilder::default().with_maximized(true),
         ..Default::default()
@@ -37,3 +167,65 @@ fn main() -> Result<()> {
     )
     .map_err(|e| Error::msg(e.to_string()))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Arguments> {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        Arguments::parse(&args)
+    }
+
+    #[test]
+    fn export_options_are_parsed() {
+        let arguments =
+            parse("config.txt --export-stl out.stl --ascii --thickness 2.5 --accuracy-v 40")
+                .unwrap();
+        assert_eq!(
+            arguments,
+            Arguments {
+                config_path: "config.txt".to_string(),
+                stl_path: Some("out.stl".to_string()),
+                stl_format: StlFormat::Ascii,
+                thickness: Some(2.5),
+                accuracy_u: None,
+                accuracy_v: Some(40),
+            }
+        );
+        let arguments = parse("--export-stl out.stl --accuracy 30").unwrap();
+        assert_eq!(arguments.config_path, Arguments::DEFAULT_CONFIG_PATH);
+        assert_eq!(
+            (arguments.accuracy_u, arguments.accuracy_v),
+            (Some(30), Some(30))
+        );
+    }
+
+    #[test]
+    fn invalid_thickness_is_rejected() {
+        for thickness in ["0", "-1", "NaN", "inf", "101", "thick"] {
+            let args = format!("--export-stl out.stl --thickness {}", thickness);
+            assert!(parse(&args).is_err(), "{}", thickness);
+        }
+    }
+
+    #[test]
+    fn invalid_accuracy_is_rejected() {
+        for accuracy in ["0", "4", "201", "-5", "10.5"] {
+            let args = format!("--export-stl out.stl --accuracy {}", accuracy);
+            assert!(parse(&args).is_err(), "{}", accuracy);
+        }
+    }
+
+    #[test]
+    fn export_options_require_export() {
+        for args in [
+            "--ascii",
+            "--thickness 5",
+            "--accuracy 10",
+            "--accuracy-u 10",
+        ] {
+            assert!(parse(args).is_err(), "{}", args);
+        }
+    }
+}
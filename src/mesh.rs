@@ -1,5 +1,12 @@
+use nalgebra::{Matrix3, Vector3};
+use rayon::prelude::*;
+
 use crate::{
+    adaptive_triangulation::{AdaptiveTriangulation, Refinement},
+    basis::BasisTable,
+    continuity::SharedEdge,
     control_points::ControlPoints,
+    geometry::Geometry,
     point::{Point, Points2DArr, PosIn2DArr},
     rotations::Rotations,
     triangle::Triangle,
@@ -9,44 +16,231 @@ use crate::{
 pub struct Mesh {
     triangles: Vec<Triangle>,
     points: Points2DArr,
+    /// Points where normal couldn't be computed from tangents and was estimated
+    degenerate_points: Vec<PosIn2DArr>,
 }
 
 impl Mesh {
-    pub fn triangulation(control_points: &ControlPoints, controls_state: &ControlsState) -> Self {
-        let points = Self::generate_points(control_points, controls_state);
-        let triangles = Self::generate_triangles(&points);
-        Self { triangles, points }
+    pub fn new(triangles: Vec<Triangle>, points: Points2DArr) -> Self {
+        Self {
+            triangles,
+            points,
+            degenerate_points: vec![],
+        }
+    }
+
+    /// Triangulates each patch into its own mesh. Patches sharing a boundary use the same
+    /// vertices along it, so no T-junctions appear between them.
+    pub fn triangulation(
+        patches: &[ControlPoints],
+        shared_edges: &[SharedEdge],
+        controls_state: &ControlsState,
+    ) -> Vec<Self> {
+        let mode = controls_state.triangulation_mode();
+        let accuracies = match mode.is_adaptive() {
+            true => vec![
+                (
+                    AdaptiveTriangulation::grid_size(),
+                    AdaptiveTriangulation::grid_size()
+                );
+                patches.len()
+            ],
+            false => controls_state.triangulation_accuracies(patches, shared_edges),
+        };
+        let evaluated: Vec<(Points2DArr, Vec<PosIn2DArr>)> = patches
+            .par_iter()
+            .zip(accuracies)
+            .map(|(control_points, (count_u, count_v))| {
+                Self::generate_points(control_points, controls_state, count_u, count_v)
+            })
+            .collect();
+        let triangles: Vec<Vec<Triangle>> = match mode.is_adaptive() {
+            true => {
+                let mut refinements: Vec<Refinement> = evaluated
+                    .par_iter()
+                    .map(|(points, _)| {
+                        AdaptiveTriangulation::refine(
+                            points,
+                            mode,
+                            controls_state.adaptive_tolerance(),
+                        )
+                    })
+                    .collect();
+                for shared_edge in shared_edges {
+                    // Shared edges are found only between patches with `patch_a < patch_b`
+                    let (head, tail) = refinements.split_at_mut(shared_edge.patch_b);
+                    Refinement::share_edge(
+                        &mut head[shared_edge.patch_a],
+                        &mut tail[0],
+                        shared_edge,
+                    );
+                }
+                refinements.par_iter().map(Refinement::triangles).collect()
+            }
+            false => evaluated
+                .par_iter()
+                .map(|(points, _)| Self::generate_triangles(points))
+                .collect(),
+        };
+        evaluated
+            .into_iter()
+            .zip(triangles)
+            .map(|((points, degenerate_points), triangles)| Self {
+                triangles,
+                points,
+                degenerate_points,
+            })
+            .collect()
+    }
+
+    /// Rotates already evaluated points, without evaluating the surface again
+    pub fn apply_rotation(&mut self, controls_state: &ControlsState) {
+        let rotation =
+            Rotations::create_rotation_matrix(controls_state.alfa(), controls_state.beta());
+        self.points
+            .as_mut_slice()
+            .par_iter_mut()
+            .for_each(|point| point.apply_rotation(&rotation));
+    }
+
+    /// Centers and scales imported meshes as a whole, so the longest side of their bounds is `size`
+    pub fn fit_to_size(meshes: &mut [Mesh], size: f32) {
+        let points = meshes
+            .iter_mut()
+            .flat_map(|mesh| mesh.points.as_mut_slice())
+            .map(|point| point.before_rotation_mut().p_mut());
+        Geometry::fit_to_size(points, size);
     }
 
     pub fn triangles(&self) -> &[Triangle] {
         &self.triangles
     }
 
+    pub fn degenerate_points(&self) -> &[PosIn2DArr] {
+        &self.degenerate_points
+    }
+
     pub fn points(&self) -> &Points2DArr {
         &self.points
     }
 
+    /// Snaps boundary vertices of different meshes which are closer than `tolerance`
+    /// to their common average, so no cracks are visible between neighbouring patches.
+    /// Normals of snapped vertices are averaged as well, keeping orientation of each mesh.
+    pub fn stitch(meshes: &mut [Mesh], tolerance: f32, controls_state: &ControlsState) {
+        let rotation =
+            Rotations::create_rotation_matrix(controls_state.alfa(), controls_state.beta());
+        let boundary: Vec<(usize, PosIn2DArr)> = meshes
+            .iter()
+            .enumerate()
+            .flat_map(|(mesh_id, mesh)| {
+                mesh.boundary_positions()
+                    .into_iter()
+                    .map(move |pos| (mesh_id, pos))
+            })
+            .collect();
+        let position = |meshes: &[Mesh], (mesh_id, pos): (usize, PosIn2DArr)| {
+            meshes[mesh_id].points.at_pos(pos).before_rotation().p()
+        };
+
+        let positions: Vec<Vector3<f32>> = boundary
+            .iter()
+            .map(|&vertex| position(meshes, vertex))
+            .collect();
+        for cluster in Geometry::cluster_by_distance(&positions, tolerance) {
+            let first_mesh = boundary[cluster[0]].0;
+            if cluster.iter().all(|&other| boundary[other].0 == first_mesh) {
+                continue;
+            }
+            Self::merge_vertices(meshes, &boundary, &cluster, &rotation);
+        }
+    }
+
+    fn merge_vertices(
+        meshes: &mut [Mesh],
+        boundary: &[(usize, PosIn2DArr)],
+        cluster: &[usize],
+        rotation: &Matrix3<f32>,
+    ) {
+        let data = |meshes: &[Mesh], id: usize| {
+            let (mesh_id, pos) = boundary[id];
+            *meshes[mesh_id].points.at_pos(pos).before_rotation()
+        };
+        let normals: Vec<Vector3<f32>> = cluster.iter().map(|&id| data(meshes, id).n()).collect();
+        let p = cluster
+            .iter()
+            .map(|&id| data(meshes, id).p())
+            .sum::<Vector3<f32>>()
+            / cluster.len() as f32;
+        let n = Geometry::aligned_average_normal(&normals);
+        for (&id, normal) in cluster.iter().zip(normals.iter()) {
+            let (mesh_id, pos) = boundary[id];
+            let point = meshes[mesh_id].points.at_mut(pos.row, pos.col);
+            let sign = normal.dot(&normals[0]).signum();
+            *point.before_rotation_mut().p_mut() = p;
+            *point.before_rotation_mut().n_mut() = n * sign;
+            point.apply_rotation(rotation);
+        }
+    }
+
+    fn boundary_positions(&self) -> Vec<PosIn2DArr> {
+        let (rows, cols) = (self.points.rows(), self.points.cols());
+        let mut positions = vec![];
+        for col in 0..cols {
+            positions.push(PosIn2DArr { row: 0, col });
+            positions.push(PosIn2DArr { row: rows - 1, col });
+        }
+        for row in 1..(rows - 1) {
+            positions.push(PosIn2DArr { row, col: 0 });
+            positions.push(PosIn2DArr { row, col: cols - 1 });
+        }
+        positions
+    }
+
     fn generate_points(
         control_points: &ControlPoints,
         controls_state: &ControlsState,
-    ) -> Points2DArr {
-        let x_rotation = Rotations::create_x_rotation_matrix(controls_state.beta());
-        let z_rotation = Rotations::create_z_rotation_matrix(controls_state.alfa());
-        let rotation = x_rotation * z_rotation;
-        let points_count = controls_state.triangulation_accuracy();
-        let mut points = Points2DArr::new(points_count, points_count);
-        for i in 0..points_count {
-            let u = i as f32 / (points_count - 1) as f32;
-            for j in 0..points_count {
-                let v = j as f32 / (points_count - 1) as f32;
-                let mut point = Point::from_control_points(u, v, control_points);
+        count_u: usize,
+        count_v: usize,
+    ) -> (Points2DArr, Vec<PosIn2DArr>) {
+        let rotation =
+            Rotations::create_rotation_matrix(controls_state.alfa(), controls_state.beta());
+        let table_u = BasisTable::new(control_points.basis_u(), count_u);
+        let table_v = BasisTable::new(control_points.basis_v(), count_v);
+        let (data, degenerate): (Vec<Point>, Vec<bool>) = (0..count_u * count_v)
+            .into_par_iter()
+            .map(|id| {
+                let (i, j) = (id / count_v, id % count_v);
+                let mut point = Point::from_basis_values(
+                    table_u.parameter(i),
+                    table_v.parameter(j),
+                    control_points,
+                    table_u.at(i),
+                    table_v.at(j),
+                );
+                let is_degenerate = point.is_degenerate();
+                if is_degenerate {
+                    point.estimate_normal(control_points);
+                }
                 point.apply_rotation(&rotation);
-                points.set_at(i, j, point);
-            }
-        }
-        points
+                (point, is_degenerate)
+            })
+            .unzip();
+        let degenerate_points = (0..count_u * count_v)
+            .filter(|&id| degenerate[id])
+            .map(|id| PosIn2DArr {
+                row: id / count_v,
+                col: id % count_v,
+            })
+            .collect();
+        (
+            Points2DArr::from_data(data, count_u, count_v),
+            degenerate_points,
+        )
     }
 
+    /// Two triangles per grid cell, counter-clockwise in (u, v), so that their
+    /// geometric normal agrees with `pu x pv`
     fn generate_triangles(points: &Points2DArr) -> Vec<Triangle> {
         let mut triangles = vec![];
         for i in 0..(points.rows() - 1) {
@@ -61,11 +255,11 @@ impl Mesh {
                 ]);
                 let bottom_triangle = Triangle::new([
                     PosIn2DArr { row: i, col: j },
-                    PosIn2DArr { row: i + 1, col: j },
                     PosIn2DArr {
                         row: i + 1,
                         col: j - 1,
                     },
+                    PosIn2DArr { row: i + 1, col: j },
                 ]);
                 triangles.push(upper_triangle);
                 triangles.push(bottom_triangle);
@@ -74,3 +268,77 @@ impl Mesh {
         triangles
     }
 }
+
+/// Fixtures shared by tests of meshes and exporters
+#[cfg(test)]
+pub mod fixtures {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::point::PData;
+
+    /// Flat unit square with two triangles, spanning x in [x, x + 1]
+    pub fn square(x: f32) -> Mesh {
+        let data = (0..4)
+            .map(|id| {
+                let (row, col) = ((id / 2) as f32, (id % 2) as f32);
+                let p = Vector3::new(x + col, row, 0.0);
+                let n = Vector3::new(0.0, 0.0, -1.0);
+                Point::new(PData::new(p, Vector3::y(), Vector3::x(), n), row, col)
+            })
+            .collect();
+        let pos = |row, col| PosIn2DArr { row, col };
+        Mesh::new(
+            vec![
+                Triangle::new([pos(0, 1), pos(0, 0), pos(1, 0)]),
+                Triangle::new([pos(0, 1), pos(1, 0), pos(1, 1)]),
+            ],
+            Points2DArr::from_data(data, 2, 2),
+        )
+    }
+
+    pub fn move_point(mesh: &mut Mesh, pos: PosIn2DArr, p: Vector3<f32>) {
+        *mesh
+            .points
+            .at_mut(pos.row, pos.col)
+            .before_rotation_mut()
+            .p_mut() = p;
+    }
+
+    /// Path in the temporary directory, unique for each test
+    pub fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "triangle_mesh_filler_{}_{}",
+            std::process::id(),
+            name
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_triangles_face_along_normals() {
+        let control_points = ControlPoints::load_from_file("config/default_config.txt").unwrap();
+        let size = 12;
+        let parameter = |i: usize| i as f32 / (size - 1) as f32;
+        let data = (0..size * size)
+            .map(|id| {
+                Point::from_control_points(
+                    parameter(id / size),
+                    parameter(id % size),
+                    &control_points[0],
+                )
+            })
+            .collect();
+        let points = Points2DArr::from_data(data, size, size);
+        for triangle in Mesh::generate_triangles(&points) {
+            let data = |i: usize| points.at_pos(triangle.vertices()[i]).before_rotation();
+            let (a, b, c) = (data(0), data(1), data(2));
+            let normal = (b.p() - a.p()).cross(&(c.p() - a.p()));
+            assert!(normal.dot(&(a.n() + b.n() + c.n())) > 0.0);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Error, Result};
+use nalgebra::{Vector2, Vector3};
+
+use crate::{
+    export::{ExportSettings, Material},
+    mesh::Mesh,
+    point::{PData, Point, Points2DArr, PosIn2DArr},
+    triangle::Triangle,
+};
+
+/// Indices of position, texture coordinates and normal of a face corner
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// Wavefront OBJ format
+pub struct Obj;
+
+impl Obj {
+    const MATERIAL_NAME: &'static str = "surface";
+
+    /// Writes all meshes as separate objects of one OBJ file, together with an MTL file
+    /// of the same name describing the material
+    pub fn export<P: AsRef<Path>>(
+        path: P,
+        meshes: &[Mesh],
+        material: &Material,
+        settings: &ExportSettings,
+    ) -> Result<()> {
+        let path = path.as_ref();
+        let mtl_path = path.with_extension("mtl");
+        Self::export_material(&mtl_path, material)?;
+
+        let file = File::create(path).context("Cannot create OBJ file")?;
+        let mut writer = BufWriter::new(file);
+        writeln!(writer, "# Exported from Triangle Mesh Filler")?;
+        if let Some(mtl_name) = mtl_path.file_name() {
+            writeln!(writer, "mtllib {}", mtl_name.to_string_lossy())?;
+        }
+        // OBJ indices are 1-based and global for the whole file
+        let mut offset = 1;
+        for (i, mesh) in meshes.iter().enumerate() {
+            let points = mesh.points();
+            writeln!(writer, "o patch_{}", i)?;
+            for row in 0..points.rows() {
+                for col in 0..points.cols() {
+                    let point = points.at(row, col);
+                    let data = settings.point_data(point);
+                    let (p, n) = (data.p(), data.n());
+                    writeln!(writer, "v {} {} {}", p.x, p.y, p.z)?;
+                    writeln!(writer, "vn {} {} {}", n.x, n.y, n.z)?;
+                    // Textures are sampled with `u` along image rows (from the top) and `v` along columns
+                    writeln!(writer, "vt {} {}", point.v(), 1.0 - point.u())?;
+                }
+            }
+            writeln!(writer, "usemtl {}", Self::MATERIAL_NAME)?;
+            for triangle in settings.oriented_triangles(mesh) {
+                write!(writer, "f")?;
+                for index in triangle {
+                    write!(writer, " {0}/{0}/{0}", offset + index)?;
+                }
+                writeln!(writer)?;
+            }
+            offset += points.rows() * points.cols();
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads triangles (polygons are split into fans) with their positions, normals and
+    /// texture coordinates. Missing normals are averaged from adjacent faces and tangents
+    /// used by normal mapping are computed from texture coordinates. All points are stored
+    /// in a single row of the mesh.
+    pub fn import<P: AsRef<Path>>(path: P) -> Result<Mesh> {
+        let file = File::open(path).context("Cannot open OBJ file")?;
+        let reader = BufReader::new(file);
+        let mut positions: Vec<Vector3<f32>> = vec![];
+        let mut uvs: Vec<Vector2<f32>> = vec![];
+        let mut normals: Vec<Vector3<f32>> = vec![];
+        let mut corners: Vec<Corner> = vec![];
+        let mut corner_ids: HashMap<Corner, usize> = HashMap::new();
+        let mut faces: Vec<[usize; 3]> = vec![];
+        for line in reader.lines() {
+            let line = line?;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("v") => positions.push(Vector3::from_iterator(Self::parse_numbers(
+                    tokens, 3, &line,
+                )?)),
+                Some("vt") => uvs.push(Vector2::from_iterator(Self::parse_numbers(
+                    tokens, 2, &line,
+                )?)),
+                Some("vn") => normals.push(Vector3::from_iterator(Self::parse_numbers(
+                    tokens, 3, &line,
+                )?)),
+                Some("f") => {
+                    let face = tokens
+                        .map(|token| {
+                            let corner = Self::parse_corner(
+                                token,
+                                positions.len(),
+                                uvs.len(),
+                                normals.len(),
+                            )?;
+                            let next_id = corners.len();
+                            let id = *corner_ids.entry(corner).or_insert(next_id);
+                            if id == next_id {
+                                corners.push(corner);
+                            }
+                            Ok(id)
+                        })
+                        .collect::<Result<Vec<usize>>>()?;
+                    if face.len() < 3 {
+                        return Err(Error::msg(format!("Invalid face: {}", line)));
+                    }
+                    faces.extend((1..(face.len() - 1)).map(|i| [face[0], face[i], face[i + 1]]));
+                }
+                // Objects, groups, materials and smoothing groups are ignored
+                _ => {}
+            }
+        }
+        if faces.is_empty() {
+            return Err(Error::msg("File does not contain any faces"));
+        }
+
+        let data = Self::compute_point_data(&corners, &faces, &positions, &uvs, &normals);
+        let points = corners
+            .iter()
+            .zip(data)
+            .map(|(&(_, uv, _), data)| {
+                // Inverse of the mapping used by `export`
+                let (u, v) = match uv {
+                    Some(uv) => (1.0 - uvs[uv].y, uvs[uv].x),
+                    None => (0.0, 0.0),
+                };
+                Point::new(data, u, v)
+            })
+            .collect::<Vec<Point>>();
+        let triangles = faces
+            .iter()
+            .map(|face| Triangle::new(face.map(|col| PosIn2DArr { row: 0, col })))
+            .collect();
+        let cols = points.len();
+        Ok(Mesh::new(
+            triangles,
+            Points2DArr::from_data(points, 1, cols),
+        ))
+    }
+
+    fn parse_numbers<'a>(
+        tokens: impl Iterator<Item = &'a str>,
+        count: usize,
+        line: &str,
+    ) -> Result<Vec<f32>> {
+        let numbers = tokens
+            .take(count)
+            .map(|token| token.parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|_| Error::msg(format!("Invalid numbers: {}", line)))?;
+        match numbers.len() == count {
+            true => Ok(numbers),
+            false => Err(Error::msg(format!("Expected {} numbers: {}", count, line))),
+        }
+    }
+
+    /// Parses `v`, `v/vt`, `v//vn` or `v/vt/vn` into 0-based indices.
+    /// Negative indices are relative to the end of lists read so far.
+    fn parse_corner(
+        token: &str,
+        positions_count: usize,
+        uvs_count: usize,
+        normals_count: usize,
+    ) -> Result<Corner> {
+        let index = |part: Option<&str>, count: usize| -> Result<Option<usize>> {
+            let part = match part {
+                Some(part) if !part.is_empty() => part,
+                _ => return Ok(None),
+            };
+            let index = part
+                .parse::<i64>()
+                .map_err(|_| Error::msg(format!("Invalid index in face: {}", token)))?;
+            let index = match index < 0 {
+                true => count as i64 + index,
+                false => index - 1,
+            };
+            match (0..count as i64).contains(&index) {
+                true => Ok(Some(index as usize)),
+                false => Err(Error::msg(format!("Index out of range in face: {}", token))),
+            }
+        };
+        let mut parts = token.split('/');
+        let position = index(parts.next(), positions_count)?
+            .ok_or_else(|| Error::msg(format!("Missing position index in face: {}", token)))?;
+        let uv = index(parts.next(), uvs_count)?;
+        let normal = index(parts.next(), normals_count)?;
+        Ok((position, uv, normal))
+    }
+
+    fn compute_point_data(
+        corners: &[Corner],
+        faces: &[[usize; 3]],
+        positions: &[Vector3<f32>],
+        uvs: &[Vector2<f32>],
+        normals: &[Vector3<f32>],
+    ) -> Vec<PData> {
+        let position = |corner: usize| positions[corners[corner].0];
+        // Tangents are accumulated per corner, so vertices on texture seams get their own
+        let mut face_normals = vec![Vector3::zeros(); positions.len()];
+        let mut tangents_u = vec![Vector3::zeros(); corners.len()];
+        let mut tangents_v = vec![Vector3::zeros(); corners.len()];
+        for &[a, b, c] in faces {
+            let e1 = position(b) - position(a);
+            let e2 = position(c) - position(a);
+            // Cross product length is twice the area, so bigger faces have more influence
+            let normal = e1.cross(&e2);
+            for corner in [a, b, c] {
+                face_normals[corners[corner].0] += normal;
+            }
+
+            let uv = |corner: usize| match corners[corner].1 {
+                Some(uv) => Vector2::new(1.0 - uvs[uv].y, uvs[uv].x),
+                None => Vector2::zeros(),
+            };
+            let (d1, d2) = (uv(b) - uv(a), uv(c) - uv(a));
+            let determinant = d1.x * d2.y - d2.x * d1.y;
+            if determinant.abs() <= f32::EPSILON {
+                continue;
+            }
+            let pu = (e1 * d2.y - e2 * d1.y) / determinant;
+            let pv = (e2 * d1.x - e1 * d2.x) / determinant;
+            for corner in [a, b, c] {
+                tangents_u[corner] += pu;
+                tangents_v[corner] += pv;
+            }
+        }
+
+        corners
+            .iter()
+            .enumerate()
+            .map(|(id, &(position, _, normal))| {
+                let n = match normal {
+                    Some(normal) => normals[normal],
+                    None => face_normals[position],
+                };
+                let n = n.try_normalize(f32::EPSILON).unwrap_or(Vector3::z());
+                // Gram-Schmidt keeps the tangent frame orthonormal
+                let pu = tangents_u[id] - n * n.dot(&tangents_u[id]);
+                let pu = match pu.try_normalize(f32::EPSILON) {
+                    Some(pu) => pu,
+                    None => Self::any_perpendicular(&n),
+                };
+                let pv = n.cross(&pu);
+                // Mirrored texture coordinates flip the second tangent
+                let pv = match pv.dot(&tangents_v[id]) < 0.0 {
+                    true => -pv,
+                    false => pv,
+                };
+                PData::new(positions[position], pu, pv, n)
+            })
+            .collect()
+    }
+
+    fn any_perpendicular(n: &Vector3<f32>) -> Vector3<f32> {
+        let axis = match n.x.abs() < 0.9 {
+            true => Vector3::x(),
+            false => Vector3::y(),
+        };
+        n.cross(&axis).normalize()
+    }
+
+    fn export_material(path: &Path, material: &Material) -> Result<()> {
+        let file = File::create(path).context("Cannot create MTL file")?;
+        let mut writer = BufWriter::new(file);
+        let [r, g, b] = material.color_rgb().map(|c| c * material.kd);
+        writeln!(writer, "newmtl {}", Self::MATERIAL_NAME)?;
+        writeln!(writer, "Kd {} {} {}", r, g, b)?;
+        writeln!(writer, "Ks {0} {0} {0}", material.ks)?;
+        writeln!(writer, "Ns {}", material.m)?;
+        writeln!(writer, "illum 2")?;
+        if let Some(texture_path) = material.texture_path {
+            writeln!(writer, "map_Kd {}", texture_path.display())?;
+        }
+        if let Some(normal_map_path) = material.normal_map_path {
+            writeln!(writer, "norm {}", normal_map_path.display())?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -2,82 +2,185 @@ use std::ops::{AddAssign, Mul};
 
 use nalgebra::{Matrix3, Vector3};
 
-use crate::control_points::ControlPoints;
+use crate::{basis::BasisValues, control_points::ControlPoints, curvature::Curvatures};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Point {
     before_rotation: PData,
     after_rotation: PData,
+    curvatures: Curvatures,
     u: f32,
     v: f32,
 }
 
 impl Point {
-    // BINOMIAL[i] = Bin(3,i)
-    const N: usize = 3;
-    const BINOMIAL: [f32; 4] = [1.0, 3.0, 3.0, 1.0];
-    const BINOMIAL_1: [f32; 3] = [1.0, 2.0, 1.0];
-
     pub const ZERO: Self = Self {
         before_rotation: PData::ZERO,
         after_rotation: PData::ZERO,
+        curvatures: Curvatures::ZERO,
         u: 0.0,
         v: 0.0,
     };
 
+    /// Point with unknown curvatures, which are left as zeros
+    pub fn new(data: PData, u: f32, v: f32) -> Self {
+        Self {
+            before_rotation: data,
+            after_rotation: data,
+            curvatures: Curvatures::ZERO,
+            u,
+            v,
+        }
+    }
+
+    /// Sine of the smallest angle between tangents for which normal is still computed from them
+    const DEGENERATE_TOLERANCE: f32 = 1e-4;
+    /// Parameter offsets of samples used to estimate normal at degenerate points
+    const ESTIMATION_OFFSETS: [f32; 3] = [1e-4, 1e-3, 1e-2];
+
+    /// Evaluates the surface, estimating normal from nearby samples at degenerate points
     pub fn from_control_points(u: f32, v: f32, control_points: &ControlPoints) -> Self {
-        let mut p = Vector3::<f32>::new(0.0, 0.0, 0.0);
-        for i in 0..(Self::N + 1) {
-            for j in 0..(Self::N + 1) {
-                p += control_points.at(i, j) * Self::calculate_B(i, u) * Self::calculate_B(j, v);
-            }
+        let mut point = Self::evaluate(u, v, control_points);
+        if point.is_degenerate() {
+            point.estimate_normal(control_points);
         }
+        point
+    }
+
+    fn evaluate(u: f32, v: f32, control_points: &ControlPoints) -> Self {
+        let basis_u = control_points.basis_u().evaluate(u);
+        let basis_v = control_points.basis_v().evaluate(v);
+        Self::from_basis_values(u, v, control_points, &basis_u, &basis_v)
+    }
+
+    /// Normal can't be computed from tangents at this point
+    pub fn is_degenerate(&self) -> bool {
+        self.before_rotation.n == Vector3::zeros()
+    }
 
-        let mut pu = Vector3::<f32>::new(0.0, 0.0, 0.0);
-        for i in 0..Self::N {
-            for j in 0..(Self::N + 1) {
-                pu += (control_points.at(i + 1, j) - control_points.at(i, j))
-                    * Self::calculate_B_1(i, u)
-                    * Self::calculate_B(j, v);
+    /// Takes normal, curvatures (and missing tangents) from the closest non-degenerate sample
+    /// towards the inside of the patch, which approximates the limit of normals
+    /// approaching this point. Falls back to Z-axis if the whole neighbourhood is degenerate.
+    pub fn estimate_normal(&mut self, control_points: &ControlPoints) {
+        let direction = |t: f32| match t < 0.5 {
+            true => 1.0,
+            false => -1.0,
+        };
+        let (du, dv) = (direction(self.u), direction(self.v));
+        let neighbour = Self::ESTIMATION_OFFSETS.iter().find_map(|offset| {
+            let neighbour =
+                Self::evaluate(self.u + du * offset, self.v + dv * offset, control_points);
+            match neighbour.is_degenerate() {
+                true => None,
+                false => Some(neighbour),
             }
+        });
+        if let Some(neighbour) = neighbour {
+            self.curvatures = neighbour.curvatures;
         }
-        pu *= Self::N as f32;
-        pu = pu.normalize();
-
-        let mut pv = Vector3::<f32>::new(0.0, 0.0, 0.0);
-        for i in 0..(Self::N + 1) {
-            for j in 0..Self::N {
-                pv += (control_points.at(i, j + 1) - control_points.at(i, j))
-                    * Self::calculate_B(i, u)
-                    * Self::calculate_B_1(j, v);
+        let data = &mut self.before_rotation;
+        let (pu, pv, n) = match neighbour.map(|neighbour| neighbour.before_rotation) {
+            Some(neighbour) => (neighbour.pu, neighbour.pv, neighbour.n),
+            None => (Vector3::x(), Vector3::y(), Vector3::z()),
+        };
+        if data.pu == Vector3::zeros() {
+            data.pu = pu;
+        }
+        if data.pv == Vector3::zeros() {
+            data.pv = pv;
+        }
+        data.n = n;
+        self.after_rotation = self.before_rotation;
+    }
+
+    /// Evaluates rational tensor product surface `S = A / W`, where
+    /// `A = sum(N_i(u) * M_j(v) * w_ij * P_ij)` and `W = sum(N_i(u) * M_j(v) * w_ij)`.
+    /// For polynomial patches all weights are equal to 1, so `W = 1`.
+    /// Derivatives of `S` follow from differentiating `A = S * W`.
+    pub fn from_basis_values(
+        u: f32,
+        v: f32,
+        control_points: &ControlPoints,
+        basis_u: &BasisValues,
+        basis_v: &BasisValues,
+    ) -> Self {
+        let mut a = Vector3::<f32>::zeros();
+        let (mut a_u, mut a_v) = (Vector3::<f32>::zeros(), Vector3::<f32>::zeros());
+        let (mut a_uu, mut a_uv, mut a_vv) = (
+            Vector3::<f32>::zeros(),
+            Vector3::<f32>::zeros(),
+            Vector3::<f32>::zeros(),
+        );
+        let (mut w, mut w_u, mut w_v) = (0.0, 0.0, 0.0);
+        let (mut w_uu, mut w_uv, mut w_vv) = (0.0, 0.0, 0.0);
+        for i in 0..control_points.rows() {
+            let (n_i, dn_i, ddn_i) = (
+                basis_u.values[i],
+                basis_u.derivatives[i],
+                basis_u.second_derivatives[i],
+            );
+            if n_i == 0.0 && dn_i == 0.0 && ddn_i == 0.0 {
+                continue;
+            }
+            for j in 0..control_points.cols() {
+                let (m_j, dm_j, ddm_j) = (
+                    basis_v.values[j],
+                    basis_v.derivatives[j],
+                    basis_v.second_derivatives[j],
+                );
+                if m_j == 0.0 && dm_j == 0.0 && ddm_j == 0.0 {
+                    continue;
+                }
+                let weight = control_points.weight(i, j);
+                let weighted_point = control_points.at(i, j) * weight;
+                a += weighted_point * n_i * m_j;
+                a_u += weighted_point * dn_i * m_j;
+                a_v += weighted_point * n_i * dm_j;
+                a_uu += weighted_point * ddn_i * m_j;
+                a_uv += weighted_point * dn_i * dm_j;
+                a_vv += weighted_point * n_i * ddm_j;
+                w += weight * n_i * m_j;
+                w_u += weight * dn_i * m_j;
+                w_v += weight * n_i * dm_j;
+                w_uu += weight * ddn_i * m_j;
+                w_uv += weight * dn_i * dm_j;
+                w_vv += weight * n_i * ddm_j;
             }
         }
-        pv *= Self::N as f32;
-        pv = pv.normalize();
 
+        let p = a / w;
+        let pu = (a_u - p * w_u) / w;
+        let pv = (a_v - p * w_v) / w;
+        let puu = (a_uu - pu * (2.0 * w_u) - p * w_uu) / w;
+        let puv = (a_uv - pu * w_v - pv * w_u - p * w_uv) / w;
+        let pvv = (a_vv - pv * (2.0 * w_v) - p * w_vv) / w;
+
+        // Where tangents vanish or are parallel (e.g. collapsed edge or cone tip)
+        // the normal is undefined and left as zero, see `estimate_normal`
         let n = pu.cross(&pv);
-        let n = n.normalize();
+        let n = match n.norm() > Self::DEGENERATE_TOLERANCE * pu.norm() * pv.norm() {
+            true => n.normalize(),
+            false => Vector3::zeros(),
+        };
+        let curvatures = Curvatures::from_derivatives(pu, pv, puu, puv, pvv, n);
+        let pu = pu
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::zeros);
+        let pv = pv
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::zeros);
 
         let before_rotation = PData::new(p, pu, pv, n);
         let after_rotation = before_rotation;
         Self {
             before_rotation,
             after_rotation,
+            curvatures,
             u,
             v,
         }
     }
 
-    #[allow(non_snake_case)]
-    fn calculate_B(i: usize, u: f32) -> f32 {
-        Self::BINOMIAL[i] * u.powi(i as i32) * (1.0 - u).powi((Self::N - i) as i32)
-    }
-
-    #[allow(non_snake_case)]
-    fn calculate_B_1(i: usize, u: f32) -> f32 {
-        Self::BINOMIAL_1[i] * u.powi(i as i32) * (1.0 - u).powi((Self::N - 1 - i) as i32)
-    }
-
     pub fn before_rotation(&self) -> &PData {
         &self.before_rotation
     }
@@ -86,6 +189,15 @@ impl Point {
         &self.after_rotation
     }
 
+    /// After modifying the data `apply_rotation` must be called to update rotated data
+    pub fn before_rotation_mut(&mut self) -> &mut PData {
+        &mut self.before_rotation
+    }
+
+    pub fn curvatures(&self) -> &Curvatures {
+        &self.curvatures
+    }
+
     pub fn u(&self) -> f32 {
         self.u
     }
@@ -116,6 +228,7 @@ impl Mul<f32> for Point {
         Point {
             after_rotation: self.after_rotation * rhs,
             before_rotation: self.before_rotation * rhs,
+            curvatures: self.curvatures * rhs,
             u: self.u * rhs,
             v: self.v * rhs,
         }
@@ -126,6 +239,7 @@ impl AddAssign<Point> for Point {
     fn add_assign(&mut self, rhs: Point) {
         self.after_rotation += rhs.after_rotation;
         self.before_rotation += rhs.before_rotation;
+        self.curvatures += rhs.curvatures;
         self.u += rhs.u;
         self.v += rhs.v;
     }
@@ -171,10 +285,20 @@ impl PData {
         self.n
     }
 
+    pub fn p_mut(&mut self) -> &mut Vector3<f32> {
+        &mut self.p
+    }
+
+    pub fn n_mut(&mut self) -> &mut Vector3<f32> {
+        &mut self.n
+    }
+
+    /// Zero vectors are left unchanged instead of becoming NaN
     pub fn normalize_all(&mut self) {
-        self.pu = self.pu.normalize();
-        self.pv = self.pv.normalize();
-        self.n = self.n.normalize();
+        let normalize = |v: Vector3<f32>| v.try_normalize(f32::EPSILON).unwrap_or(v);
+        self.pu = normalize(self.pu);
+        self.pv = normalize(self.pv);
+        self.n = normalize(self.n);
     }
 
     pub fn n_with_normal_map(&self, normal_map_n: Vector3<f32>) -> Vector3<f32> {
@@ -212,10 +336,10 @@ pub struct Points2DArr {
 }
 
 impl Points2DArr {
-    pub fn new(height: usize, width: usize) -> Self {
-        let vec = vec![Point::ZERO; width * height];
+    pub fn from_data(data: Vec<Point>, height: usize, width: usize) -> Self {
+        assert_eq!(data.len(), height * width);
         Self {
-            data: vec,
+            data,
             rows: height,
             cols: width,
         }
@@ -229,13 +353,21 @@ impl Points2DArr {
         &self.data[self.get_index(row, column)]
     }
 
-    pub fn at_pos(&self, pos: PosIn2DArr) -> &Point {
-        self.at(pos.row, pos.col)
+    pub fn at_mut(&mut self, row: usize, column: usize) -> &mut Point {
+        let id = self.get_index(row, column);
+        &mut self.data[id]
     }
 
-    pub fn set_at(&mut self, row: usize, column: usize, point: Point) {
-        let id = self.get_index(row, column);
-        self.data[id] = point;
+    pub fn as_slice(&self) -> &[Point] {
+        &self.data
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [Point] {
+        &mut self.data
+    }
+
+    pub fn at_pos(&self, pos: PosIn2DArr) -> &Point {
+        self.at(pos.row, pos.col)
     }
 
     pub fn rows(&self) -> usize {
@@ -245,6 +377,16 @@ impl Points2DArr {
     pub fn cols(&self) -> usize {
         self.cols
     }
+
+    /// Returns `(min, max)` of Z coordinates after rotation
+    pub fn z_range(&self) -> (f32, f32) {
+        self.data
+            .iter()
+            .map(|point| point.after_rotation().p().z)
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), z| {
+                (min.min(z), max.max(z))
+            })
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -252,3 +394,54 @@ pub struct PosIn2DArr {
     pub row: usize,
     pub col: usize,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rational_quarter_cylinder_is_exact_circle() {
+        const RADIUS: f32 = 200.0;
+        let patches =
+            ControlPoints::load_from_file("config/quarter_cylinder_rational.txt").unwrap();
+        for i in 0..=20 {
+            for v in [0.0, 0.5, 1.0] {
+                let u = i as f32 / 20.0;
+                let point = Point::from_control_points(u, v, &patches[0]);
+                let data = point.before_rotation();
+                let distance = data.p().x.hypot(data.p().z);
+                assert!((distance - RADIUS).abs() < 1e-3, "u = {}: {}", u, distance);
+                // Normal of a cylinder points along the radius
+                let radial = Vector3::new(data.p().x, 0.0, data.p().z) / distance;
+                assert!((data.n().dot(&radial).abs() - 1.0).abs() < 1e-4);
+                assert!(data.pu().dot(&radial).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn quarter_cylinder_curvatures() {
+        const RADIUS: f32 = 200.0;
+        for file in [
+            "config/quarter_cylinder_rational.txt",
+            "config/half_cylinder_nurbs.txt",
+        ] {
+            let patches = ControlPoints::load_from_file(file).unwrap();
+            for u in [0.1, 0.5, 0.9] {
+                for v in [0.1, 0.5, 0.9] {
+                    let curvatures = *Point::from_control_points(u, v, &patches[0]).curvatures();
+                    let message = format!("{} at ({}, {}): {:?}", file, u, v, curvatures);
+                    // Normals point out of the cylinder, so it bends away from them
+                    assert!(
+                        (curvatures.min_principal + 1.0 / RADIUS).abs() < 1e-5,
+                        "{}",
+                        message
+                    );
+                    assert!(curvatures.max_principal.abs() < 1e-5, "{}", message);
+                    assert!(curvatures.gaussian.abs() < 1e-7, "{}", message);
+                    assert!((curvatures.mean + 0.5 / RADIUS).abs() < 1e-5, "{}", message);
+                }
+            }
+        }
+    }
+}
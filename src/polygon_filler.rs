@@ -1,50 +1,77 @@
-use egui::{Color32, Pos2};
+use egui::Color32;
 use nalgebra::{Vector2, Vector3};
 
 use crate::{
     colors_manager::ColorsManager,
-    drawer::Drawer,
+    curvature::CurvatureMap,
+    debug_view::DebugView,
+    depth_buffer::DepthBuffer,
+    fog::Fog,
     light_source::LightSource,
     point::{Point, Points2DArr, PosIn2DArr},
+    shading::{ShadingMode, ToonSettings, ZebraSettings},
 };
 
 #[derive(Clone)]
-pub struct PolygonFiller<'p, 'd, 'ep, 'l, 'tl, 'nm> {
+pub struct PolygonFiller<'p, 'l, 'tl, 'nm, 'db> {
     all_points: &'p Points2DArr,
-    drawer: &'d Drawer<'ep>,
     light_source: &'l LightSource,
     colors_manager: ColorsManager<'tl, 'nm>,
     kd: f32,
     ks: f32,
     m: u8,
     use_normal_map: bool,
+    fog: Fog,
+    shading_mode: ShadingMode,
+    toon: ToonSettings,
+    zebra: ZebraSettings,
+    debug_view: DebugView,
+    curvature_map: CurvatureMap,
+    z_range: (f32, f32),
+    depth_buffer: &'db DepthBuffer,
 }
 
-impl<'p, 'd, 'ep, 'l, 'tl, 'nm> PolygonFiller<'p, 'd, 'ep, 'l, 'tl, 'nm> {
+impl<'p, 'l, 'tl, 'nm, 'db> PolygonFiller<'p, 'l, 'tl, 'nm, 'db> {
+    const VIEW_VECTOR: Vector3<f32> = Vector3::new(0.0, 0.0, 1.0);
+
     #[allow(clippy::too_many_arguments)]
     pub fn new(
         all_points: &'p Points2DArr,
-        drawer: &'d Drawer<'ep>,
         light_source: &'l LightSource,
         colors_manager: ColorsManager<'tl, 'nm>,
         kd: f32,
         ks: f32,
         m: u8,
         use_normal_map: bool,
+        fog: Fog,
+        shading_mode: ShadingMode,
+        toon: ToonSettings,
+        zebra: ZebraSettings,
+        debug_view: DebugView,
+        curvature_map: CurvatureMap,
+        z_range: (f32, f32),
+        depth_buffer: &'db DepthBuffer,
     ) -> Self {
         Self {
             all_points,
-            drawer,
             light_source,
             colors_manager,
             kd,
             ks,
             m,
             use_normal_map,
+            fog,
+            shading_mode,
+            toon,
+            zebra,
+            debug_view,
+            curvature_map,
+            z_range,
+            depth_buffer,
         }
     }
 
-    pub fn fill_polygon(&mut self, polygon: &[PosIn2DArr]) {
+    pub fn fill_polygon(&mut self, polygon: &[PosIn2DArr], triangle_id: usize) {
         let mut aet = AET::new();
         let sorted_indicies = self.get_vertices_sorted_indicies(polygon);
         let y_min = self
@@ -91,7 +118,7 @@ impl<'p, 'd, 'ep, 'l, 'tl, 'nm> PolygonFiller<'p, 'd, 'ep, 'l, 'tl, 'nm> {
             }
             aet.sort_by_x();
             let f = |x: i32, y: i32| {
-                self.paint_pixel(x, y, polygon);
+                self.paint_pixel(x, y, polygon, triangle_id);
             };
             aet.fill_line(y, f);
             aet.update_x();
@@ -99,15 +126,16 @@ impl<'p, 'd, 'ep, 'l, 'tl, 'nm> PolygonFiller<'p, 'd, 'ep, 'l, 'tl, 'nm> {
         }
     }
 
-    fn paint_pixel(&self, x: i32, y: i32, polygon: &[PosIn2DArr]) {
-        let pos = Pos2 {
-            x: x as f32,
-            y: y as f32,
+    fn paint_pixel(&self, x: i32, y: i32, polygon: &[PosIn2DArr], triangle_id: usize) {
+        let bar_coords =
+            self.get_barycentric_coords(polygon, Vector2::<f32>::new(x as f32, y as f32));
+        let p = self.point_from_barycentric_coords(polygon, &bar_coords);
+        let color = match self.debug_view {
+            DebugView::Off => self.color_in_point(p),
+            _ => self.debug_color_in_point(p, &bar_coords, triangle_id),
         };
-        let bar_coords = self.get_barycentric_coords(polygon, Vector2::<f32>::new(pos.x, pos.y));
-        let p = self.point_from_barycentric_coords(polygon, bar_coords);
-        let color = self.color_in_point(p);
-        self.drawer.paint_pixel(pos, color);
+        self.depth_buffer
+            .paint(x, y, p.after_rotation().p().z, color);
     }
 
     fn check_point(
@@ -132,8 +160,7 @@ impl<'p, 'd, 'ep, 'l, 'tl, 'nm> PolygonFiller<'p, 'd, 'ep, 'l, 'tl, 'nm> {
             let b = polygon[j];
             let ya = self.all_points.at_pos(a).after_rotation().p().y;
             let yb = self.all_points.at_pos(b).after_rotation().p().y;
-            ya.partial_cmp(&yb)
-                .expect("All floats in polygon should be comparable")
+            ya.total_cmp(&yb)
         });
         indicies
     }
@@ -167,7 +194,7 @@ impl<'p, 'd, 'ep, 'l, 'tl, 'nm> PolygonFiller<'p, 'd, 'ep, 'l, 'tl, 'nm> {
         vec![u, v, w]
     }
 
-    fn point_from_barycentric_coords(&self, polygon: &[PosIn2DArr], bars: Vec<f32>) -> Point {
+    fn point_from_barycentric_coords(&self, polygon: &[PosIn2DArr], bars: &[f32]) -> Point {
         let mut p = Point::ZERO;
         for i in 0..polygon.len() {
             p += *self.all_points.at_pos(polygon[i]) * bars[i];
@@ -177,42 +204,120 @@ impl<'p, 'd, 'ep, 'l, 'tl, 'nm> PolygonFiller<'p, 'd, 'ep, 'l, 'tl, 'nm> {
     }
 
     fn color_in_point(&self, point: Point) -> Color32 {
-        let base_color = self.colors_manager.get_point_base_color(&point);
+        let n = self
+            .colors_manager
+            .get_point_n_vector(&point, self.use_normal_map);
+        let color = match self.shading_mode {
+            ShadingMode::Toon
+                if self
+                    .toon
+                    .is_outline(point.after_rotation().n(), Self::VIEW_VECTOR) =>
+            {
+                self.toon.outline_color
+            }
+            // Reflection lines show the geometry, so the normal map is not applied
+            ShadingMode::Zebra => self
+                .zebra
+                .color(point.after_rotation().n(), Self::VIEW_VECTOR),
+            _ => self.lit_color(&point, n),
+        };
+        self.fog.apply(color, point.after_rotation().p().z)
+    }
+
+    fn debug_color_in_point(
+        &self,
+        point: Point,
+        bar_coords: &[f32],
+        triangle_id: usize,
+    ) -> Color32 {
+        let point_data = point.after_rotation();
+        match self.debug_view {
+            DebugView::Off => self.color_in_point(point),
+            DebugView::GeometricNormal => DebugView::vector_color(point_data.n()),
+            DebugView::MappedNormal => DebugView::vector_color(
+                self.colors_manager
+                    .get_point_n_vector(&point, self.use_normal_map),
+            ),
+            DebugView::TangentU => DebugView::vector_color(point_data.pu()),
+            DebugView::TangentV => DebugView::vector_color(point_data.pv()),
+            DebugView::Uv => DebugView::unit_color(point.u(), point.v(), 0.0),
+            DebugView::Depth => {
+                let (z_min, z_max) = self.z_range;
+                let depth = match z_max - z_min > f32::EPSILON {
+                    true => (point_data.p().z - z_min) / (z_max - z_min),
+                    false => 1.0,
+                };
+                DebugView::unit_color(depth, depth, depth)
+            }
+            DebugView::Barycentric => {
+                DebugView::unit_color(bar_coords[0], bar_coords[1], bar_coords[2])
+            }
+            DebugView::TriangleId => DebugView::id_color(triangle_id),
+            DebugView::Curvature => self.curvature_map.color(point.curvatures()),
+            DebugView::DiffuseOnly | DebugView::SpecularOnly => {
+                let n = self
+                    .colors_manager
+                    .get_point_n_vector(&point, self.use_normal_map);
+                let (diffuse, specular) = self.calculate_light_terms(&point, n);
+                match self.debug_view {
+                    DebugView::DiffuseOnly => self.color_from_light_terms(&point, diffuse, 0.0),
+                    _ => self.color_from_light_terms(&point, 0.0, specular),
+                }
+            }
+        }
+    }
+
+    fn lit_color(&self, point: &Point, n: Vector3<f32>) -> Color32 {
+        let (diffuse, specular) = self.calculate_light_terms(point, n);
+        let (diffuse, specular) = match self.shading_mode {
+            ShadingMode::Phong | ShadingMode::Zebra => (diffuse, specular),
+            ShadingMode::Toon => (
+                self.toon.quantise_diffuse(diffuse),
+                self.toon.quantise_specular(specular),
+            ),
+        };
+        self.color_from_light_terms(point, diffuse, specular)
+    }
+
+    fn color_from_light_terms(&self, point: &Point, diffuse: f32, specular: f32) -> Color32 {
+        let base_color = self.colors_manager.get_point_base_color(point);
         let r = base_color.r() as f32 / u8::MAX as f32;
         let light_r = self.light_source.color().r() as f32 / u8::MAX as f32;
         let g = base_color.g() as f32 / u8::MAX as f32;
         let light_g = self.light_source.color().g() as f32 / u8::MAX as f32;
         let b = base_color.b() as f32 / u8::MAX as f32;
         let light_b = self.light_source.color().b() as f32 / u8::MAX as f32;
-        let new_r = self.calculate_color_component(&point, r, light_r);
-        let new_g = self.calculate_color_component(&point, g, light_g);
-        let new_b = self.calculate_color_component(&point, b, light_b);
+        let new_r = self.calculate_color_component(r, light_r, diffuse, specular);
+        let new_g = self.calculate_color_component(g, light_g, diffuse, specular);
+        let new_b = self.calculate_color_component(b, light_b, diffuse, specular);
         Color32::from_rgb(new_r, new_g, new_b)
     }
 
-    fn calculate_color_component(
-        &self,
-        point: &Point,
-        base_color_component: f32,
-        light_color_component: f32,
-    ) -> u8 {
+    /// Returns `(cos(N, L), cos^m(V, R))`, both clamped to be non-negative
+    fn calculate_light_terms(&self, point: &Point, n: Vector3<f32>) -> (f32, f32) {
         let point_data = point.after_rotation();
-        let n = self
-            .colors_manager
-            .get_point_n_vector(point, self.use_normal_map);
         let l = (self.light_source.position() - point_data.p()).normalize();
         let mut cos_n_l = n.dot(&l);
         if cos_n_l < 0.0 {
             cos_n_l = 0.0;
         }
-        let lhs = self.kd * light_color_component * base_color_component * cos_n_l;
-        let v = Vector3::<f32>::new(0.0, 0.0, 1.0);
         let r = 2.0 * cos_n_l * n - l;
-        let mut cos_m_v_r = v.dot(&r).powi(self.m as i32);
+        let mut cos_m_v_r = Self::VIEW_VECTOR.dot(&r).powi(self.m as i32);
         if cos_m_v_r < 0.0 {
             cos_m_v_r = 0.0;
         }
-        let rhs = self.ks * light_color_component * base_color_component * cos_m_v_r;
+        (cos_n_l, cos_m_v_r)
+    }
+
+    fn calculate_color_component(
+        &self,
+        base_color_component: f32,
+        light_color_component: f32,
+        diffuse: f32,
+        specular: f32,
+    ) -> u8 {
+        let lhs = self.kd * light_color_component * base_color_component * diffuse;
+        let rhs = self.ks * light_color_component * base_color_component * specular;
         let mut sum = lhs + rhs;
         if sum > 1.0 {
             sum = 1.0;
@@ -274,10 +379,7 @@ impl AET {
     }
 
     fn sort_by_x(&mut self) {
-        self.data.sort_by(|a, b| {
-            a.x.partial_cmp(&b.x)
-                .expect("All floats in polygon should be comparable")
-        });
+        self.data.sort_by(|a, b| a.x.total_cmp(&b.x));
     }
 
     fn fill_line<F>(&mut self, y: i32, pixel_callback: F)
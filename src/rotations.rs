@@ -3,6 +3,11 @@ use nalgebra::Matrix3;
 pub struct Rotations;
 
 impl Rotations {
+    /// Rotation applied to the whole surface: first around Z-axis by `alfa`, then around X-axis by `beta`
+    pub fn create_rotation_matrix(alfa: f32, beta: f32) -> Matrix3<f32> {
+        Self::create_x_rotation_matrix(beta) * Self::create_z_rotation_matrix(alfa)
+    }
+
     pub fn create_x_rotation_matrix(beta: f32) -> Matrix3<f32> {
         let beta = beta.to_radians();
         let sin_beta = beta.sin();
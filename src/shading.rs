@@ -0,0 +1,113 @@
+use egui::Color32;
+use nalgebra::Vector3;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ShadingMode {
+    Phong,
+    Toon,
+    Zebra,
+}
+
+impl ShadingMode {
+    pub const ALL: [ShadingMode; 3] = [ShadingMode::Phong, ShadingMode::Toon, ShadingMode::Zebra];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ShadingMode::Phong => "Phong",
+            ShadingMode::Toon => "Toon",
+            ShadingMode::Zebra => "Zebra",
+        }
+    }
+}
+
+/// Parameters of the cel shading mode
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ToonSettings {
+    /// Number of discrete levels the diffuse term is quantised into
+    pub bands: u8,
+    /// Specular term above this value is drawn as a fully lit spot
+    pub specular_threshold: f32,
+    /// Pixels whose normal has smaller `|cos|` with the view direction are drawn as outline
+    pub outline_threshold: f32,
+    pub outline_color: Color32,
+}
+
+impl ToonSettings {
+    pub fn quantise_diffuse(&self, cos_n_l: f32) -> f32 {
+        let bands = self.bands.max(1) as f32;
+        ((cos_n_l * bands).ceil() / bands).clamp(0.0, 1.0)
+    }
+
+    pub fn quantise_specular(&self, cos_m_v_r: f32) -> f32 {
+        match cos_m_v_r > self.specular_threshold {
+            true => 1.0,
+            false => 0.0,
+        }
+    }
+
+    pub fn is_outline(&self, n: Vector3<f32>, view: Vector3<f32>) -> bool {
+        n.dot(&view).abs() < self.outline_threshold
+    }
+}
+
+impl Default for ToonSettings {
+    fn default() -> Self {
+        Self {
+            bands: 4,
+            specular_threshold: 0.5,
+            outline_threshold: 0.25,
+            outline_color: Color32::BLACK,
+        }
+    }
+}
+
+/// Parameters of the zebra (reflection lines) mode. Surface reflects an environment of
+/// parallel stripes, so stripes on the surface are continuous where the surface is G1
+/// and bend smoothly where it is G2. Kinks and jumps reveal discontinuities.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ZebraSettings {
+    /// Number of dark stripes across the whole environment
+    pub stripes: u8,
+    /// Fraction of each stripe period which is dark
+    pub width: f32,
+    /// Angle in degrees between the stripes and the screen X-axis
+    pub direction: f32,
+    pub dark_color: Color32,
+    pub light_color: Color32,
+}
+
+impl ZebraSettings {
+    /// Color of stripe reflected in direction of `view` by surface with normal `n`.
+    /// Normals facing away from the viewer are flipped, so patches with opposite
+    /// orientation show the same stripes.
+    pub fn color(&self, n: Vector3<f32>, view: Vector3<f32>) -> Color32 {
+        let Some(n) = n.try_normalize(f32::EPSILON) else {
+            return self.dark_color;
+        };
+        let n = match n.dot(&view) < 0.0 {
+            true => -n,
+            false => n,
+        };
+        let reflected = 2.0 * n.dot(&view) * n - view;
+        // Stripes are planes perpendicular to `across`, indexed by the angle of the reflected ray
+        let angle = self.direction.to_radians();
+        let across = Vector3::new(-angle.sin(), angle.cos(), 0.0);
+        let position = reflected.dot(&across).clamp(-1.0, 1.0).asin() / std::f32::consts::PI + 0.5;
+        match (position * self.stripes.max(1) as f32).fract() < self.width {
+            true => self.dark_color,
+            false => self.light_color,
+        }
+    }
+}
+
+impl Default for ZebraSettings {
+    fn default() -> Self {
+        Self {
+            stripes: 12,
+            width: 0.5,
+            direction: 0.0,
+            dark_color: Color32::BLACK,
+            light_color: Color32::WHITE,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use nalgebra::Vector3;
+
+use crate::{export::ExportSettings, geometry::Geometry, mesh::Mesh};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+impl StlFormat {
+    pub const ALL: [StlFormat; 2] = [StlFormat::Ascii, StlFormat::Binary];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            StlFormat::Ascii => "ASCII",
+            StlFormat::Binary => "Binary",
+        }
+    }
+}
+
+type Facet = [Vector3<f32>; 3];
+
+/// STL format used by 3D printing software
+pub struct Stl;
+
+impl Stl {
+    const SOLID_NAME: &'static str = "surface";
+
+    /// Writes triangles of all meshes. When thickening, vertices closer than
+    /// `weld_tolerance` are treated as one, so patches sharing a boundary form one solid.
+    pub fn export<P: AsRef<Path>>(
+        path: P,
+        meshes: &[Mesh],
+        settings: &ExportSettings,
+        weld_tolerance: f32,
+    ) -> Result<()> {
+        let facets: Vec<Facet> = match settings.thicken {
+            true => Self::thickened_facets(meshes, settings, weld_tolerance),
+            false => meshes
+                .iter()
+                .flat_map(|mesh| Self::surface_facets(mesh, settings))
+                .collect(),
+        };
+        let file = File::create(path).context("Cannot create STL file")?;
+        let mut writer = BufWriter::new(file);
+        match settings.stl_format {
+            StlFormat::Ascii => Self::write_ascii(&mut writer, &facets)?,
+            StlFormat::Binary => Self::write_binary(&mut writer, &facets)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn surface_facets(mesh: &Mesh, settings: &ExportSettings) -> Vec<Facet> {
+        let points = mesh.points();
+        let position = |i: usize| settings.point_data(&points.as_slice()[i]).p();
+        settings
+            .oriented_triangles(mesh)
+            .into_iter()
+            .map(|[a, b, c]| [position(a), position(b), position(c)])
+            .collect()
+    }
+
+    /// Closed solid made of the surface, its copy moved by `thickness` against normals
+    /// and walls connecting their boundaries. Facets are oriented so that their
+    /// normals point outside of the solid. Vertices of all meshes are welded first,
+    /// so boundaries shared by patches (or seams of imported meshes) get no walls.
+    fn thickened_facets(meshes: &[Mesh], settings: &ExportSettings, tolerance: f32) -> Vec<Facet> {
+        let mut positions = vec![];
+        let mut normals = vec![];
+        let mut triangles = vec![];
+        for mesh in meshes {
+            let offset = positions.len();
+            for point in mesh.points().as_slice() {
+                let data = settings.point_data(point);
+                positions.push(data.p());
+                normals.push(data.n());
+            }
+            triangles.extend(
+                settings
+                    .oriented_triangles(mesh)
+                    .into_iter()
+                    .map(|triangle| triangle.map(|i| offset + i)),
+            );
+        }
+        let (positions, normals, welded) = Self::weld(&positions, &normals, tolerance);
+        // Triangles with welded vertices (e.g. at collapsed edges of a patch) have no area
+        let triangles: Vec<[usize; 3]> = triangles
+            .into_iter()
+            .map(|triangle| triangle.map(|i| welded[i]))
+            .filter(|&[a, b, c]| a != b && b != c && c != a)
+            .collect();
+        let top = |i: usize| positions[i];
+        let bottom = |i: usize| positions[i] - normals[i] * settings.thickness;
+
+        let mut facets = Vec::with_capacity(triangles.len() * 2);
+        for &[a, b, c] in triangles.iter() {
+            facets.push([top(a), top(b), top(c)]);
+            facets.push([bottom(a), bottom(c), bottom(b)]);
+        }
+        for (a, b) in Self::boundary_edges(&triangles) {
+            facets.push([top(b), top(a), bottom(a)]);
+            facets.push([top(b), bottom(a), bottom(b)]);
+        }
+        facets
+    }
+
+    /// Merges vertices closer than `tolerance` into their average, with the average
+    /// normal. Returns merged positions and normals, and the merged index of each vertex.
+    fn weld(
+        positions: &[Vector3<f32>],
+        normals: &[Vector3<f32>],
+        tolerance: f32,
+    ) -> (Vec<Vector3<f32>>, Vec<Vector3<f32>>, Vec<usize>) {
+        let clusters = Geometry::cluster_by_distance(positions, tolerance);
+        let mut welded = vec![0; positions.len()];
+        let mut welded_positions = Vec::with_capacity(clusters.len());
+        let mut welded_normals = Vec::with_capacity(clusters.len());
+        for (welded_id, cluster) in clusters.iter().enumerate() {
+            for &id in cluster {
+                welded[id] = welded_id;
+            }
+            let position = cluster
+                .iter()
+                .map(|&id| positions[id])
+                .sum::<Vector3<f32>>();
+            let cluster_normals: Vec<Vector3<f32>> =
+                cluster.iter().map(|&id| normals[id]).collect();
+            welded_positions.push(position / cluster.len() as f32);
+            welded_normals.push(Geometry::aligned_average_normal(&cluster_normals));
+        }
+        (welded_positions, welded_normals, welded)
+    }
+
+    /// Directed edges `(a, b)` which belong to exactly one triangle
+    fn boundary_edges(triangles: &[[usize; 3]]) -> Vec<(usize, usize)> {
+        let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+        for &[a, b, c] in triangles.iter() {
+            for (from, to) in [(a, b), (b, c), (c, a)] {
+                *counts.entry((from.min(to), from.max(to))).or_insert(0) += 1;
+            }
+        }
+        triangles
+            .iter()
+            .flat_map(|&[a, b, c]| [(a, b), (b, c), (c, a)])
+            .filter(|&(from, to)| counts[&(from.min(to), from.max(to))] == 1)
+            .collect()
+    }
+
+    fn facet_normal(facet: &Facet) -> Vector3<f32> {
+        let normal = (facet[1] - facet[0]).cross(&(facet[2] - facet[0]));
+        normal
+            .try_normalize(f32::EPSILON)
+            .unwrap_or_else(Vector3::zeros)
+    }
+
+    fn write_ascii<W: Write>(writer: &mut W, facets: &[Facet]) -> Result<()> {
+        writeln!(writer, "solid {}", Self::SOLID_NAME)?;
+        for facet in facets {
+            let n = Self::facet_normal(facet);
+            writeln!(writer, "  facet normal {} {} {}", n.x, n.y, n.z)?;
+            writeln!(writer, "    outer loop")?;
+            for v in facet {
+                writeln!(writer, "      vertex {} {} {}", v.x, v.y, v.z)?;
+            }
+            writeln!(writer, "    endloop")?;
+            writeln!(writer, "  endfacet")?;
+        }
+        writeln!(writer, "endsolid {}", Self::SOLID_NAME)?;
+        Ok(())
+    }
+
+    fn write_binary<W: Write>(writer: &mut W, facets: &[Facet]) -> Result<()> {
+        const HEADER_SIZE: usize = 80;
+        let mut header = [0u8; HEADER_SIZE];
+        let title = b"Exported from Triangle Mesh Filler";
+        header[..title.len()].copy_from_slice(title);
+        writer.write_all(&header)?;
+        writer.write_all(&(facets.len() as u32).to_le_bytes())?;
+        for facet in facets {
+            let n = Self::facet_normal(facet);
+            for v in std::iter::once(&n).chain(facet.iter()) {
+                for c in v.iter() {
+                    writer.write_all(&c.to_le_bytes())?;
+                }
+            }
+            // Attribute byte count, unused
+            writer.write_all(&0u16.to_le_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::fixtures::square;
+
+    #[test]
+    fn thickened_patches_form_one_closed_solid() {
+        let meshes = [square(0.0), square(1.0)];
+        let facets = Stl::thickened_facets(&meshes, &ExportSettings::default(), 0.01);
+        // 4 triangles on each side and walls along 6 outer edges, none along the shared one
+        assert_eq!(facets.len(), 2 * 4 + 2 * 6);
+
+        // Every directed edge of a closed, consistently oriented solid has a reversed twin
+        let key = |v: &Vector3<f32>| v.map(f32::to_bits);
+        let mut edges: HashMap<_, i32> = HashMap::new();
+        for facet in facets.iter() {
+            for i in 0..3 {
+                let (from, to) = (key(&facet[i]), key(&facet[(i + 1) % 3]));
+                *edges.entry((from, to)).or_insert(0) += 1;
+            }
+        }
+        for (&(from, to), &count) in edges.iter() {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(to, from)), Some(&1));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Error, Result};
 use egui::{Color32, ColorImage};
@@ -9,24 +9,35 @@ use crate::point::Point;
 
 pub struct TextureLoader {
     texture: Option<ColorImage>,
+    path: Option<PathBuf>,
 }
 
 impl TextureLoader {
     pub fn new() -> Self {
-        Self { texture: None }
+        Self {
+            texture: None,
+            path: None,
+        }
     }
 
     pub fn load_texture_from_file<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
-        let image = ImageReader::open(path)?.decode()?;
+        let image = ImageReader::open(&path)?.decode()?;
         let size = [image.width() as _, image.height() as _];
         let image_buffer = image.to_rgba8();
         let pixels = image_buffer.as_flat_samples();
         self.texture = Some(ColorImage::from_rgba_unmultiplied(size, pixels.as_slice()));
+        self.path = Some(path.as_ref().to_path_buf());
         Ok(())
     }
 
     pub fn remove_texture(&mut self) {
-        self.texture = None
+        self.texture = None;
+        self.path = None;
+    }
+
+    /// Path of the file the current texture was loaded from
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
     }
 
     pub fn has_texture(&self) -> bool {
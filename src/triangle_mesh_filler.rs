@@ -8,8 +8,26 @@ use rayon::prelude::*;
 use rfd::FileDialog;
 
 use crate::{
-    colors_manager::ColorsManager, control_points::ControlPoints, drawer::Drawer,
-    light_source::LightSource, mesh::Mesh, polygon_filler::PolygonFiller,
+    adaptive_triangulation::TriangulationMode,
+    colors_manager::ColorsManager,
+    continuity::{PatchEdge, SharedEdge},
+    control_point_editor::ControlPointEditor,
+    control_point_inspector::ControlPointInspector,
+    control_points::ControlPoints,
+    curvature::{CurvatureKind, CurvatureMap},
+    debug_view::DebugView,
+    depth_buffer::DepthBuffer,
+    drawer::{Drawer, VectorGlyphs, Wireframe},
+    export::{ExportSettings, Material},
+    fog::{Fog, FogMode},
+    gltf::Gltf,
+    history::{History, LightSettings, Snapshot},
+    light_source::LightSource,
+    mesh::Mesh,
+    obj::Obj,
+    polygon_filler::PolygonFiller,
+    shading::{ShadingMode, ToonSettings, ZebraSettings},
+    stl::{Stl, StlFormat},
     texture_loader::TextureLoader,
 };
 
@@ -18,45 +36,139 @@ pub struct TriangleMeshFiller {
     controls_state: ControlsState,
     previous_controls_state: ControlsState,
     need_mesh_recalculation: bool,
-    control_points: ControlPoints,
-    mesh: Mesh,
+    need_mesh_rotation: bool,
+    need_reshading: bool,
+    previous_light_source: LightSource,
+    depth_buffer: Option<DepthBuffer>,
+    frame_texture: Option<egui::TextureHandle>,
+    patches: Vec<ControlPoints>,
+    /// Patches as loaded from the file, so edits can be reverted
+    loaded_patches: Vec<ControlPoints>,
+    meshes: Vec<Mesh>,
+    shared_edges: Vec<SharedEdge>,
+    control_point_editor: ControlPointEditor,
+    control_point_inspector: ControlPointInspector,
     light_source: LightSource,
     texture_loader: TextureLoader,
     normal_map_loader: TextureLoader,
+    /// Error of the last failed file operation, shown in the controls panel
+    file_error: Option<String>,
+    history: History,
+    export_settings: ExportSettings,
 }
 
 impl TriangleMeshFiller {
+    // Size of the longest side of imported models, so they fit the viewport
+    const FIT_SIZE: f32 = 500.0;
+
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let controls_state = ControlsState::default();
-        let control_points = ControlPoints::load_from_file(path)?;
-        let mesh = Mesh::triangulation(&control_points, &controls_state);
+        let (patches, shared_edges, meshes) = match Self::has_extension(&path, "obj") {
+            true => {
+                let mut meshes = vec![Obj::import(&path)?];
+                Mesh::fit_to_size(&mut meshes, Self::FIT_SIZE);
+                (vec![], vec![], meshes)
+            }
+            false => {
+                let patches = Self::load_patches(&path)?;
+                let shared_edges = SharedEdge::find_all(&patches);
+                let meshes = Self::triangulate_patches(&patches, &shared_edges, &controls_state);
+                (patches, shared_edges, meshes)
+            }
+        };
         let light_source = LightSource::new(400.0, egui::Color32::LIGHT_GREEN, 100.0);
         let texture_loader = TextureLoader::new();
         let normal_map_loader = TextureLoader::new();
+        let history = History::new(Snapshot {
+            patches: patches.clone(),
+            controls_state,
+            light: LightSettings::from_light_source(&light_source),
+            texture_path: None,
+            normal_map_path: None,
+        });
         Ok(Self {
             animation_start_time: Instant::now(),
-            control_points,
-            mesh,
+            loaded_patches: patches.clone(),
+            patches,
+            meshes,
+            shared_edges,
+            control_point_editor: ControlPointEditor::default(),
+            control_point_inspector: ControlPointInspector::default(),
             previous_controls_state: controls_state,
             controls_state,
+            previous_light_source: light_source,
             light_source,
             texture_loader,
             normal_map_loader,
             need_mesh_recalculation: true,
+            need_mesh_rotation: false,
+            need_reshading: true,
+            depth_buffer: None,
+            frame_texture: None,
+            file_error: None,
+            history,
+            export_settings: ExportSettings::default(),
         })
     }
 
+    fn has_extension<P: AsRef<Path>>(path: P, extension: &str) -> bool {
+        path.as_ref()
+            .extension()
+            .is_some_and(|path_extension| path_extension.eq_ignore_ascii_case(extension))
+    }
+
+    fn load_patches<P: AsRef<Path>>(path: P) -> Result<Vec<ControlPoints>> {
+        match Self::has_extension(&path, "bpt") {
+            true => {
+                let mut patches = ControlPoints::load_from_bpt_file(path)?;
+                ControlPoints::fit_to_size(&mut patches, Self::FIT_SIZE);
+                Ok(patches)
+            }
+            false => ControlPoints::load_from_file(path),
+        }
+    }
+
     fn recalculate_mesh(&mut self) {
-        if self.need_mesh_recalculation {
-            let new_mesh = Mesh::triangulation(&self.control_points, &self.controls_state);
-            self.mesh = new_mesh;
+        // Meshes imported from OBJ files have no surface to evaluate again, they can only be rotated
+        if self.need_mesh_recalculation && !self.patches.is_empty() {
+            self.meshes =
+                Self::triangulate_patches(&self.patches, &self.shared_edges, &self.controls_state);
             self.need_mesh_recalculation = false;
+            self.need_mesh_rotation = false;
+            self.need_reshading = true;
+        } else if self.need_mesh_rotation || self.need_mesh_recalculation {
+            for mesh in self.meshes.iter_mut() {
+                mesh.apply_rotation(&self.controls_state);
+            }
+            self.need_mesh_recalculation = false;
+            self.need_mesh_rotation = false;
+            self.need_reshading = true;
         }
     }
 
+    fn triangulate_patches(
+        patches: &[ControlPoints],
+        shared_edges: &[SharedEdge],
+        controls_state: &ControlsState,
+    ) -> Vec<Mesh> {
+        let mut meshes = Mesh::triangulation(patches, shared_edges, controls_state);
+        if controls_state.stitch_patches() {
+            Mesh::stitch(
+                &mut meshes,
+                controls_state.stitch_tolerance(),
+                controls_state,
+            );
+        }
+        meshes
+    }
+
     fn show_controls(&mut self, ctx: &egui::Context) {
         const SPACING_X: f32 = 30.0;
         const SPACING_Y: f32 = 25.0;
+        let textures_before = (
+            self.texture_loader.has_texture(),
+            self.normal_map_loader.has_texture(),
+        );
         egui::SidePanel::right("ControlsPanle")
             .resizable(false)
             .show(ctx, |ui| {
@@ -65,16 +177,109 @@ impl TriangleMeshFiller {
                 ui.vertical(|ui| {
                     ui.spacing_mut().item_spacing.y = SPACING_Y;
                     ui.horizontal(|ui| {
-                        ui.add(
-                            egui::Slider::new(
-                                &mut self.controls_state.triangulation_accuracy,
-                                5..=60,
-                            )
-                            .text("Triangulation accuracy"),
-                        );
+                        egui::ComboBox::from_label("Triangulation")
+                            .selected_text(self.controls_state.triangulation_mode.label())
+                            .show_ui(ui, |ui| {
+                                for mode in TriangulationMode::ALL {
+                                    ui.selectable_value(
+                                        &mut self.controls_state.triangulation_mode,
+                                        mode,
+                                        mode.label(),
+                                    );
+                                }
+                            });
                         ui.add_space(SPACING_X);
+                        if self.controls_state.triangulation_mode.is_adaptive() {
+                            ui.add(
+                                egui::Slider::new(
+                                    &mut self.controls_state.adaptive_tolerance,
+                                    0.1..=50.0,
+                                )
+                                .logarithmic(true)
+                                .text("Tolerance"),
+                            );
+                            ui.add_space(SPACING_X);
+                        }
                         ui.checkbox(&mut self.controls_state.show_mesh, "Show mesh");
                     });
+                    if !self.controls_state.triangulation_mode.is_adaptive() {
+                        ui.horizontal(|ui| {
+                            let derive = self.controls_state.derive_accuracy_from_net;
+                            ui.add(
+                                egui::Slider::new(
+                                    &mut self.controls_state.triangulation_accuracy_u,
+                                    ControlsState::MIN_ACCURACY..=ControlsState::MAX_ACCURACY,
+                                )
+                                .text(match derive {
+                                    true => "Accuracy (longer side)",
+                                    false => "Accuracy u",
+                                }),
+                            );
+                            ui.add_space(SPACING_X);
+                            ui.add_enabled(
+                                !derive,
+                                egui::Slider::new(
+                                    &mut self.controls_state.triangulation_accuracy_v,
+                                    ControlsState::MIN_ACCURACY..=ControlsState::MAX_ACCURACY,
+                                )
+                                .text("Accuracy v"),
+                            );
+                            ui.add_space(SPACING_X);
+                            ui.checkbox(
+                                &mut self.controls_state.derive_accuracy_from_net,
+                                "Derive from control net",
+                            );
+                        });
+                    }
+                    ui.add_enabled_ui(self.controls_state.show_mesh, |ui| {
+                        ui.horizontal(|ui| {
+                            let wireframe = &mut self.controls_state.wireframe;
+                            ui.checkbox(&mut wireframe.hidden_line, "Hidden lines removal");
+                            ui.add_space(SPACING_X);
+                            ui.checkbox(&mut wireframe.grid_only, "Only u/v lines");
+                            ui.add_space(SPACING_X);
+                            ui.label("Edge color");
+                            ui.color_edit_button_srgba(&mut wireframe.color);
+                            ui.add_space(SPACING_X);
+                            ui.add(
+                                egui::Slider::new(&mut wireframe.thickness, 0.5..=5.0)
+                                    .text("Thickness"),
+                            );
+                        });
+                    });
+                    self.show_degenerate_points_warning(ui);
+                    if self.patches.len() > 1 {
+                        self.show_patches_controls(ui, SPACING_X);
+                    }
+                    egui::CollapsingHeader::new("Control points").show(ui, |ui| {
+                        if self.control_point_inspector.show(
+                            ui,
+                            &mut self.patches,
+                            &self.loaded_patches,
+                            self.control_point_editor.selected(),
+                        ) {
+                            self.on_control_points_changed();
+                        }
+                    });
+                    self.show_file_controls(ui);
+                    self.show_history_controls(ui);
+                    ui.horizontal(|ui| {
+                        let glyphs = &mut self.controls_state.vector_glyphs;
+                        ui.label("Vectors:");
+                        ui.checkbox(&mut glyphs.show_n, "n");
+                        ui.checkbox(&mut glyphs.show_pu, "pu");
+                        ui.checkbox(&mut glyphs.show_pv, "pv");
+                        ui.add_enabled_ui(glyphs.any_visible(), |ui| {
+                            ui.add_space(SPACING_X);
+                            ui.add(
+                                egui::Slider::new(&mut glyphs.length, 5.0..=100.0).text("Length"),
+                            );
+                            ui.add_space(SPACING_X);
+                            ui.add(
+                                egui::Slider::new(&mut glyphs.step, 1..=10).text("Sample every"),
+                            );
+                        });
+                    });
                     ui.horizontal(|ui| {
                         ui.add(
                             egui::Slider::new(&mut self.controls_state.alfa, -45.0..=45.0)
@@ -97,6 +302,83 @@ impl TriangleMeshFiller {
                         ui.add_space(SPACING_X);
                         ui.add(egui::Slider::new(&mut self.controls_state.m, 1..=100).text("m"));
                     });
+                    ui.horizontal(|ui| {
+                        egui::ComboBox::from_label("Shading")
+                            .selected_text(self.controls_state.shading_mode.label())
+                            .show_ui(ui, |ui| {
+                                for mode in ShadingMode::ALL {
+                                    ui.selectable_value(
+                                        &mut self.controls_state.shading_mode,
+                                        mode,
+                                        mode.label(),
+                                    );
+                                }
+                            });
+                        if self.controls_state.shading_mode == ShadingMode::Toon {
+                            let toon = &mut self.controls_state.toon;
+                            ui.add_space(SPACING_X);
+                            ui.add(egui::Slider::new(&mut toon.bands, 1..=10).text("Bands"));
+                            ui.add_space(SPACING_X);
+                            ui.add(
+                                egui::Slider::new(&mut toon.specular_threshold, 0.0..=1.0)
+                                    .text("Specular threshold"),
+                            );
+                        }
+                        if self.controls_state.shading_mode == ShadingMode::Zebra {
+                            let zebra = &mut self.controls_state.zebra;
+                            ui.add_space(SPACING_X);
+                            ui.add(egui::Slider::new(&mut zebra.stripes, 1..=50).text("Stripes"));
+                            ui.add_space(SPACING_X);
+                            ui.add(
+                                egui::Slider::new(&mut zebra.width, 0.05..=0.95)
+                                    .text("Stripe width"),
+                            );
+                        }
+                    });
+                    if self.controls_state.shading_mode == ShadingMode::Toon {
+                        ui.horizontal(|ui| {
+                            let toon = &mut self.controls_state.toon;
+                            ui.add(
+                                egui::Slider::new(&mut toon.outline_threshold, 0.0..=1.0)
+                                    .text("Outline threshold"),
+                            );
+                            ui.add_space(SPACING_X);
+                            ui.label("Outline color");
+                            ui.color_edit_button_srgba(&mut toon.outline_color);
+                        });
+                    }
+                    if self.controls_state.shading_mode == ShadingMode::Zebra {
+                        ui.horizontal(|ui| {
+                            let zebra = &mut self.controls_state.zebra;
+                            ui.add(
+                                egui::Slider::new(&mut zebra.direction, 0.0..=180.0)
+                                    .suffix("°")
+                                    .text("Direction"),
+                            );
+                            ui.add_space(SPACING_X);
+                            ui.label("Stripe colors");
+                            ui.color_edit_button_srgba(&mut zebra.dark_color);
+                            ui.color_edit_button_srgba(&mut zebra.light_color);
+                        });
+                    }
+                    let previous_debug_view = self.controls_state.debug_view;
+                    egui::ComboBox::from_label("Debug view")
+                        .selected_text(self.controls_state.debug_view.label())
+                        .show_ui(ui, |ui| {
+                            for view in DebugView::ALL {
+                                ui.selectable_value(
+                                    &mut self.controls_state.debug_view,
+                                    view,
+                                    view.label(),
+                                );
+                            }
+                        });
+                    if self.controls_state.debug_view == DebugView::Curvature {
+                        if previous_debug_view != DebugView::Curvature {
+                            self.fit_curvature_range();
+                        }
+                        self.show_curvature_controls(ui, SPACING_X);
+                    }
                     ui.vertical(|ui| {
                         ui.label("Texture always take precedence over color. To use shape color texture must be removed.");
                         ui.horizontal(|ui| {
@@ -170,25 +452,433 @@ impl TriangleMeshFiller {
                         );
                         ui.add_space(SPACING_X);
                         ui.checkbox(&mut self.controls_state.run_animation, "Run animation");
-                    })
+                    });
+                    ui.horizontal(|ui| {
+                        let fog = &mut self.controls_state.fog;
+                        ui.checkbox(&mut fog.enabled, "Fog");
+                        ui.add_space(SPACING_X);
+                        ui.add_enabled_ui(fog.enabled, |ui| {
+                            egui::ComboBox::from_id_salt("FogMode")
+                                .selected_text(fog.mode.label())
+                                .show_ui(ui, |ui| {
+                                    for mode in FogMode::ALL {
+                                        ui.selectable_value(&mut fog.mode, mode, mode.label());
+                                    }
+                                });
+                            ui.add_space(SPACING_X);
+                            ui.label("Fog color");
+                            ui.color_edit_button_srgba(&mut fog.color);
+                        });
+                    });
+                    ui.add_enabled_ui(self.controls_state.fog.enabled, |ui| {
+                        ui.horizontal(|ui| {
+                            let fog = &mut self.controls_state.fog;
+                            ui.add(
+                                egui::Slider::new(&mut fog.start_z, -500.0..=500.0)
+                                    .text("Fog start Z"),
+                            );
+                            ui.add_space(SPACING_X);
+                            match fog.mode {
+                                FogMode::Linear => ui.add(
+                                    egui::Slider::new(&mut fog.end_z, -1000.0..=500.0)
+                                        .text("Fog end Z"),
+                                ),
+                                FogMode::Exponential | FogMode::ExponentialSquared => ui.add(
+                                    egui::Slider::new(&mut fog.density, 0.0..=0.05)
+                                        .text("Fog density"),
+                                ),
+                            };
+                        });
+                    });
                 });
             });
 
-        let values_changed = self.controls_state != self.previous_controls_state;
-        if values_changed {
+        let textures_after = (
+            self.texture_loader.has_texture(),
+            self.normal_map_loader.has_texture(),
+        );
+        self.track_changes(textures_before != textures_after);
+    }
+
+    /// Decides which stages of the pipeline must run again: surface evaluation,
+    /// rotation of evaluated points or only shading of pixels
+    fn track_changes(&mut self, textures_changed: bool) {
+        let current = &self.controls_state;
+        let previous = &self.previous_controls_state;
+        if current.surface_differs(previous) {
             self.need_mesh_recalculation = true;
-            self.previous_controls_state = self.controls_state;
+        } else if current.rotation_differs(previous) {
+            self.need_mesh_rotation = true;
         }
+        if current.shading_differs(previous)
+            || textures_changed
+            || self.light_source != self.previous_light_source
+        {
+            self.need_reshading = true;
+        }
+        self.previous_controls_state = self.controls_state;
+        self.previous_light_source = self.light_source;
     }
 
-    fn show_central_panel(&self, ctx: &egui::Context) {
-        egui::CentralPanel::default().show(ctx, |ui| {
-            let painter = ui.painter();
-            let screen_center = ui.available_rect_before_wrap().center();
-            let drawer = Drawer::new(screen_center, painter);
+    fn show_file_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(!self.patches.is_empty(), egui::Button::new("Save as…"))
+                .clicked()
+            {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("Control points", &["txt"])
+                    .set_file_name("control_points.txt")
+                    .save_file()
+                {
+                    self.file_error = ControlPoints::save_to_file(&self.patches, path)
+                        .err()
+                        .map(|error| format!("Cannot save control points: {:#}", error));
+                }
+            }
+            if ui.button("Export OBJ…").clicked() {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("Wavefront OBJ", &["obj"])
+                    .set_file_name("surface.obj")
+                    .save_file()
+                {
+                    self.file_error =
+                        Obj::export(path, &self.meshes, &self.material(), &self.export_settings)
+                            .err()
+                            .map(|error| format!("Cannot export OBJ: {:#}", error));
+                }
+            }
+            if ui.button("Export glTF…").clicked() {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("glTF", &["gltf"])
+                    .set_file_name("surface.gltf")
+                    .save_file()
+                {
+                    self.file_error =
+                        Gltf::export(path, &self.meshes, &self.material(), &self.export_settings)
+                            .err()
+                            .map(|error| format!("Cannot export glTF: {:#}", error));
+                }
+            }
+            if ui.button("Export STL…").clicked() {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("STL", &["stl"])
+                    .set_file_name("surface.stl")
+                    .save_file()
+                {
+                    self.file_error = self
+                        .export_stl(path)
+                        .err()
+                        .map(|error| format!("Cannot export STL: {:#}", error));
+                }
+            }
+            ui.checkbox(&mut self.export_settings.rotated, "Export rotated")
+                .on_hover_text("Export the surface as it is seen instead of its original position");
+        });
+        ui.horizontal(|ui| {
+            let settings = &mut self.export_settings;
+            egui::ComboBox::from_label("STL format")
+                .selected_text(settings.stl_format.label())
+                .show_ui(ui, |ui| {
+                    for format in StlFormat::ALL {
+                        ui.selectable_value(&mut settings.stl_format, format, format.label());
+                    }
+                });
+            ui.checkbox(&mut settings.thicken, "Thicken into solid");
+            ui.add_enabled(
+                settings.thicken,
+                egui::DragValue::new(&mut settings.thickness)
+                    .range(ExportSettings::THICKNESS_RANGE)
+                    .speed(0.1)
+                    .prefix("Thickness "),
+            );
+        });
+        if let Some(error) = &self.file_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    }
+
+    /// Writes current meshes to STL file, can be used without showing the window
+    pub fn export_stl<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        Stl::export(
+            path,
+            &self.meshes,
+            &self.export_settings,
+            self.controls_state.stitch_tolerance(),
+        )
+    }
+
+    /// Overrides the number of samples along `u` and `v` of the uniform triangulation
+    pub fn set_triangulation_accuracy(
+        &mut self,
+        accuracy_u: Option<usize>,
+        accuracy_v: Option<usize>,
+    ) {
+        let state = &mut self.controls_state;
+        state.triangulation_accuracy_u = accuracy_u.unwrap_or(state.triangulation_accuracy_u);
+        state.triangulation_accuracy_v = accuracy_v.unwrap_or(state.triangulation_accuracy_v);
+        self.need_mesh_recalculation = true;
+        self.recalculate_mesh();
+    }
+
+    pub fn export_settings_mut(&mut self) -> &mut ExportSettings {
+        &mut self.export_settings
+    }
+
+    fn material(&self) -> Material<'_> {
+        Material {
+            color: self.controls_state.shape_color(),
+            kd: self.controls_state.kd(),
+            ks: self.controls_state.ks(),
+            m: self.controls_state.m(),
+            texture_path: self.texture_loader.path(),
+            normal_map_path: self.normal_map_loader.path(),
+        }
+    }
+
+    fn show_history_controls(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.history.can_undo(), egui::Button::new("Undo"))
+                .on_hover_text("Ctrl+Z")
+                .clicked()
+            {
+                self.undo();
+            }
+            if ui
+                .add_enabled(self.history.can_redo(), egui::Button::new("Redo"))
+                .on_hover_text("Ctrl+Shift+Z")
+                .clicked()
+            {
+                self.redo();
+            }
+        });
+        let mut jump_to = None;
+        egui::CollapsingHeader::new("History").show(ui, |ui| {
+            egui::ScrollArea::vertical()
+                .id_salt("History")
+                .max_height(150.0)
+                .show(ui, |ui| {
+                    let current = self.history.current_index();
+                    for (i, label) in self.history.labels().enumerate() {
+                        if ui.selectable_label(i == current, label).clicked() && i != current {
+                            jump_to = Some(i);
+                        }
+                    }
+                });
+        });
+        if let Some(snapshot) = jump_to.and_then(|i| self.history.jump_to(i).cloned()) {
+            self.restore(snapshot);
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            patches: self.patches.clone(),
+            controls_state: self
+                .controls_state
+                .with_view_state_of(&ControlsState::default()),
+            light: LightSettings::from_light_source(&self.light_source),
+            texture_path: self.texture_loader.path().map(Path::to_path_buf),
+            normal_map_path: self.normal_map_loader.path().map(Path::to_path_buf),
+        }
+    }
+
+    /// Adds the current state to the history. While any pointer button is held,
+    /// continuous changes (dragging sliders or control points) are coalesced into one entry.
+    fn record_history(&mut self, ctx: &egui::Context) {
+        let pointer_down = ctx.input(|i| i.pointer.any_down());
+        let snapshot = self.snapshot();
+        self.history.record(snapshot, pointer_down);
+    }
+
+    fn handle_history_shortcuts(&mut self, ctx: &egui::Context) {
+        let redo = egui::KeyboardShortcut::new(
+            egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+            egui::Key::Z,
+        );
+        let undo = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+        // Undo shortcut matches also when shift is held, so redo must be checked first
+        if ctx.input_mut(|i| i.consume_shortcut(&redo)) {
+            self.redo();
+        } else if ctx.input_mut(|i| i.consume_shortcut(&undo)) {
+            self.undo();
+        }
+    }
+
+    fn undo(&mut self) {
+        if let Some(snapshot) = self.history.undo().cloned() {
+            self.restore(snapshot);
+        }
+    }
+
+    fn redo(&mut self) {
+        if let Some(snapshot) = self.history.redo().cloned() {
+            self.restore(snapshot);
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        if snapshot.patches != self.patches {
+            self.patches = snapshot.patches;
+            self.on_control_points_changed();
+        }
+        self.controls_state = snapshot
+            .controls_state
+            .with_view_state_of(&self.controls_state);
+        snapshot.light.apply_to(&mut self.light_source);
+        for (loader, path) in [
+            (&mut self.texture_loader, snapshot.texture_path),
+            (&mut self.normal_map_loader, snapshot.normal_map_path),
+        ] {
+            if loader.path() == path.as_deref() {
+                continue;
+            }
+            match path {
+                Some(path) => {
+                    if let Err(error) = loader.load_texture_from_file(&path) {
+                        loader.remove_texture();
+                        self.file_error =
+                            Some(format!("Cannot load {}: {:#}", path.display(), error));
+                    }
+                }
+                None => loader.remove_texture(),
+            }
+        }
+        self.need_reshading = true;
+    }
+
+    fn on_control_points_changed(&mut self) {
+        self.shared_edges = SharedEdge::find_all(&self.patches);
+        self.need_mesh_recalculation = true;
+    }
+
+    fn show_curvature_controls(&mut self, ui: &mut egui::Ui, spacing_x: f32) {
+        ui.horizontal(|ui| {
+            let previous_kind = self.controls_state.curvature_map.kind;
+            egui::ComboBox::from_label("Curvature")
+                .selected_text(self.controls_state.curvature_map.kind.label())
+                .show_ui(ui, |ui| {
+                    for kind in CurvatureKind::ALL {
+                        ui.selectable_value(
+                            &mut self.controls_state.curvature_map.kind,
+                            kind,
+                            kind.label(),
+                        );
+                    }
+                });
+            // Different curvatures have different magnitudes, e.g. Gaussian is a product of two others
+            if self.controls_state.curvature_map.kind != previous_kind {
+                self.fit_curvature_range();
+            }
+            ui.add_space(spacing_x);
+            let curvature_map = &mut self.controls_state.curvature_map;
+            let speed = ((curvature_map.max - curvature_map.min).abs() * 0.01).max(1e-7) as f64;
+            ui.add(
+                egui::DragValue::new(&mut curvature_map.min)
+                    .speed(speed)
+                    .max_decimals(6)
+                    .range(f32::NEG_INFINITY..=curvature_map.max)
+                    .prefix("Min: "),
+            );
+            ui.add(
+                egui::DragValue::new(&mut curvature_map.max)
+                    .speed(speed)
+                    .max_decimals(6)
+                    .range(curvature_map.min..=f32::INFINITY)
+                    .prefix("Max: "),
+            );
+            if ui
+                .button("Fit to surface")
+                .on_hover_text("Sets the range to values on the surface, ignoring extreme outliers")
+                .clicked()
+            {
+                self.fit_curvature_range();
+            }
+        });
+    }
+
+    fn fit_curvature_range(&mut self) {
+        let curvatures = self
+            .meshes
+            .iter()
+            .flat_map(|mesh| mesh.points().as_slice())
+            .map(|point| point.curvatures());
+        self.controls_state.curvature_map.fit_range(curvatures);
+    }
+
+    fn show_degenerate_points_warning(&self, ui: &mut egui::Ui) {
+        let degenerate: Vec<(usize, usize)> = self
+            .meshes
+            .iter()
+            .enumerate()
+            .map(|(patch, mesh)| (patch, mesh.degenerate_points().len()))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        if degenerate.is_empty() {
+            return;
+        }
+        let total: usize = degenerate.iter().map(|&(_, count)| count).sum();
+        let patches: Vec<String> = degenerate
+            .iter()
+            .map(|(patch, count)| format!("patch {}: {}", patch, count))
+            .collect();
+        ui.colored_label(
+            Drawer::DEGENERATE_POINT_COLOR,
+            format!("Normals estimated at {} degenerate points", total),
+        )
+        .on_hover_text(format!(
+            "Tangents vanish or are parallel there (e.g. collapsed edge), \
+             so normals are taken from nearby samples. Points are marked when the mesh is shown.\n{}",
+            patches.join("\n")
+        ));
+    }
+
+    fn show_patches_controls(&mut self, ui: &mut egui::Ui, spacing_x: f32) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.controls_state.stitch_patches, "Stitch patches");
+            ui.add_space(spacing_x);
+            ui.add_enabled(
+                self.controls_state.stitch_patches,
+                egui::Slider::new(&mut self.controls_state.stitch_tolerance, 0.0..=20.0)
+                    .text("Stitch tolerance"),
+            );
+        });
+        egui::CollapsingHeader::new(format!(
+            "Patch continuity ({} patches, {} shared edges)",
+            self.patches.len(),
+            self.shared_edges.len()
+        ))
+        .show(ui, |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(150.0)
+                .show(ui, |ui| {
+                    if self.shared_edges.is_empty() {
+                        ui.label("No shared edges found");
+                    }
+                    for edge in self.shared_edges.iter() {
+                        ui.label(format!(
+                            "Patch {} ({}) - patch {} ({}): {}",
+                            edge.patch_a, edge.edge_a, edge.patch_b, edge.edge_b, edge.continuity
+                        ));
+                    }
+                });
+        });
+    }
+
+    /// Fills all triangles into new frame buffer and uploads it as a texture
+    fn shade_frame(&mut self, ctx: &egui::Context) {
+        let depth_buffer = DepthBuffer::new(&self.meshes);
+        let z_range = self.meshes.iter().map(|mesh| mesh.points().z_range()).fold(
+            (f32::INFINITY, f32::NEG_INFINITY),
+            |(min, max), (z_min, z_max)| (min.min(z_min), max.max(z_max)),
+        );
+
+        const CHUNK_SIZE: usize = 512;
+        let mut triangle_id_offset = 0;
+        for mesh in self.meshes.iter() {
             let pf = PolygonFiller::new(
-                self.mesh.points(),
-                &drawer,
+                mesh.points(),
                 &self.light_source,
                 ColorsManager::new(
                     self.controls_state.shape_color(),
@@ -199,43 +889,131 @@ impl TriangleMeshFiller {
                 self.controls_state.ks(),
                 self.controls_state.m(),
                 self.controls_state.use_normal_map(),
+                self.controls_state.fog(),
+                self.controls_state.shading_mode(),
+                self.controls_state.toon(),
+                self.controls_state.zebra(),
+                self.controls_state.debug_view(),
+                self.controls_state.curvature_map(),
+                z_range,
+                &depth_buffer,
             );
 
-            self.mesh.triangles().par_chunks(512).for_each(|chunk| {
-                chunk.iter().for_each(|triangle| {
-                    let mut pf_clone = pf.clone();
-                    pf_clone.fill_polygon(triangle.vertices());
+            mesh.triangles()
+                .par_chunks(CHUNK_SIZE)
+                .enumerate()
+                .for_each(|(chunk_id, chunk)| {
+                    chunk.iter().enumerate().for_each(|(i, triangle)| {
+                        let mut pf_clone = pf.clone();
+                        let triangle_id = triangle_id_offset + chunk_id * CHUNK_SIZE + i;
+                        pf_clone.fill_polygon(triangle.vertices(), triangle_id);
+                    });
                 });
-            });
+            triangle_id_offset += mesh.triangles().len();
+        }
+
+        let image = depth_buffer.to_color_image();
+        match self.frame_texture.as_mut() {
+            Some(texture) => texture.set(image, egui::TextureOptions::NEAREST),
+            None => {
+                self.frame_texture =
+                    Some(ctx.load_texture("Surface", image, egui::TextureOptions::NEAREST))
+            }
+        }
+        self.depth_buffer = Some(depth_buffer);
+        self.need_reshading = false;
+    }
+
+    fn show_central_panel(&mut self, ctx: &egui::Context) {
+        egui::CentralPanel::default().show(ctx, |ui| {
+            let viewport = ui.available_rect_before_wrap();
+            let screen_center = viewport.center();
+            // Control points can be edited only when they are visible
+            if self.controls_state.show_mesh() {
+                let response = ui.interact(
+                    viewport,
+                    ui.id().with("Viewport"),
+                    egui::Sense::click_and_drag(),
+                );
+                if self.control_point_editor.handle_input(
+                    &response,
+                    screen_center,
+                    &mut self.patches,
+                    &self.controls_state,
+                ) {
+                    self.on_control_points_changed();
+                    self.recalculate_mesh();
+                }
+            }
+            if self.need_reshading || self.depth_buffer.is_none() {
+                self.shade_frame(ctx);
+            }
+
+            let painter = ui.painter();
+            let drawer = Drawer::new(screen_center, painter);
+            if let (Some(texture), Some(depth_buffer)) = (&self.frame_texture, &self.depth_buffer) {
+                drawer.draw_frame(texture, depth_buffer);
+            }
 
             if self.controls_state.show_mesh() {
-                drawer.draw_control_points(&self.control_points, &self.controls_state);
-                drawer.draw_mesh(&self.mesh);
+                let wireframe = self.controls_state.wireframe();
+                let depth_buffer = match wireframe.hidden_line {
+                    true => self.depth_buffer.as_ref(),
+                    false => None,
+                };
+                for mesh in self.meshes.iter() {
+                    drawer.draw_mesh(mesh, &wireframe, depth_buffer);
+                    drawer.draw_degenerate_points(mesh);
+                }
+                for (patch, control_points) in self.patches.iter().enumerate() {
+                    drawer.draw_control_points(
+                        patch,
+                        control_points,
+                        &self.controls_state,
+                        &self.control_point_editor,
+                    );
+                }
+            }
+            let vector_glyphs = self.controls_state.vector_glyphs();
+            if vector_glyphs.any_visible() {
+                for mesh in self.meshes.iter() {
+                    drawer.draw_vector_glyphs(mesh.points(), &vector_glyphs);
+                }
             }
             if self.controls_state.show_light_source() {
                 drawer.draw_light_source(&self.light_source);
             }
+            if self.controls_state.debug_view() == DebugView::Curvature {
+                drawer.draw_curvature_legend(viewport, &self.controls_state.curvature_map());
+            }
         });
     }
 }
 
 impl eframe::App for TriangleMeshFiller {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.handle_history_shortcuts(ctx);
+        self.show_controls(ctx);
         self.recalculate_mesh();
         if self.controls_state.run_animation() {
             ctx.request_repaint_after(Duration::from_millis(16));
             let elapsed = self.animation_start_time.elapsed().as_secs_f32();
             let t = elapsed * 0.5;
             self.light_source.update_position(t);
+            self.need_reshading = true;
         }
-        self.show_controls(ctx);
         self.show_central_panel(ctx);
+        self.record_history(ctx);
     }
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct ControlsState {
-    triangulation_accuracy: usize,
+    triangulation_accuracy_u: usize,
+    triangulation_accuracy_v: usize,
+    derive_accuracy_from_net: bool,
+    triangulation_mode: TriangulationMode,
+    adaptive_tolerance: f32,
     alfa: f32,
     beta: f32,
     show_mesh: bool,
@@ -246,11 +1024,142 @@ pub struct ControlsState {
     show_light_source: bool,
     run_animation: bool,
     use_normal_map: bool,
+    fog: Fog,
+    shading_mode: ShadingMode,
+    toon: ToonSettings,
+    zebra: ZebraSettings,
+    debug_view: DebugView,
+    curvature_map: CurvatureMap,
+    vector_glyphs: VectorGlyphs,
+    wireframe: Wireframe,
+    stitch_patches: bool,
+    stitch_tolerance: f32,
 }
 
 impl ControlsState {
-    pub fn triangulation_accuracy(&self) -> usize {
-        self.triangulation_accuracy
+    pub const MIN_ACCURACY: usize = 5;
+    pub const MAX_ACCURACY: usize = 200;
+    const MIN_DERIVED_ACCURACY: usize = 2;
+
+    /// Returns true if surface must be evaluated again
+    pub fn surface_differs(&self, other: &ControlsState) -> bool {
+        // Screen-space error depends on the rotation, so the triangulation must be redone
+        let screen_space_rotation = self.triangulation_mode
+            == TriangulationMode::AdaptiveScreenSpace
+            && self.rotation_differs(other);
+        self.triangulation_accuracy_u != other.triangulation_accuracy_u
+            || self.triangulation_accuracy_v != other.triangulation_accuracy_v
+            || self.derive_accuracy_from_net != other.derive_accuracy_from_net
+            || self.triangulation_mode != other.triangulation_mode
+            || self.adaptive_tolerance != other.adaptive_tolerance
+            || screen_space_rotation
+            || self.stitch_patches != other.stitch_patches
+            || self.stitch_tolerance != other.stitch_tolerance
+    }
+
+    pub fn rotation_differs(&self, other: &ControlsState) -> bool {
+        self.alfa != other.alfa || self.beta != other.beta
+    }
+
+    /// Returns true if anything that affects colors of the filled surface differs.
+    /// Overlays drawn on top of the surface are not taken into account.
+    pub fn shading_differs(&self, other: &ControlsState) -> bool {
+        *self != other.with_overlays_of(self)
+    }
+
+    /// Copy of `self` with overlays drawn on top of the surface and animation taken from `other`
+    fn with_overlays_of(&self, other: &ControlsState) -> ControlsState {
+        ControlsState {
+            show_mesh: other.show_mesh,
+            wireframe: other.wireframe,
+            vector_glyphs: other.vector_glyphs,
+            show_light_source: other.show_light_source,
+            run_animation: other.run_animation,
+            ..*self
+        }
+    }
+
+    /// Copy of `self` with view-only state (overlays, animation and debug view) taken from
+    /// `other`. Such state is kept out of undo history, so undo doesn't step through toggles.
+    fn with_view_state_of(&self, other: &ControlsState) -> ControlsState {
+        ControlsState {
+            debug_view: other.debug_view,
+            curvature_map: other.curvature_map,
+            ..self.with_overlays_of(other)
+        }
+    }
+
+    /// Returns numbers of samples along `u` and `v` for each patch in the uniform triangulation.
+    /// Patches sharing a boundary get the larger of their sample counts along it, so both
+    /// sample the boundary at the same parameters and their vertices can be stitched.
+    pub fn triangulation_accuracies(
+        &self,
+        patches: &[ControlPoints],
+        shared_edges: &[SharedEdge],
+    ) -> Vec<(usize, usize)> {
+        let mut accuracies: Vec<(usize, usize)> = patches
+            .iter()
+            .map(|control_points| self.triangulation_accuracy(control_points))
+            .collect();
+        // Raising the count along one boundary changes the opposite boundary of the patch
+        // as well, so counts are propagated until nothing changes. They only grow, so it ends.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for shared_edge in shared_edges {
+                let sides = [
+                    (shared_edge.patch_a, shared_edge.edge_a),
+                    (shared_edge.patch_b, shared_edge.edge_b),
+                ];
+                let count = sides
+                    .iter()
+                    .map(|&(patch, edge)| *Self::count_along(&mut accuracies[patch], edge))
+                    .max()
+                    .unwrap_or_default();
+                for (patch, edge) in sides {
+                    let current = Self::count_along(&mut accuracies[patch], edge);
+                    if *current != count {
+                        *current = count;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        accuracies
+    }
+
+    /// Sample count of `accuracy` that determines samples along `edge`
+    fn count_along(accuracy: &mut (usize, usize), edge: PatchEdge) -> &mut usize {
+        match edge {
+            PatchEdge::U0 | PatchEdge::U1 => &mut accuracy.1,
+            PatchEdge::V0 | PatchEdge::V1 => &mut accuracy.0,
+        }
+    }
+
+    /// Returns number of samples along `u` and `v` for given patch in the uniform triangulation
+    fn triangulation_accuracy(&self, control_points: &ControlPoints) -> (usize, usize) {
+        if !self.derive_accuracy_from_net {
+            return (self.triangulation_accuracy_u, self.triangulation_accuracy_v);
+        }
+        // Keep ratio of samples equal to ratio of control net sizes, so triangles are roughly square
+        let (length_u, length_v) = control_points.control_net_lengths();
+        let longer = length_u.max(length_v);
+        if longer <= f32::EPSILON {
+            return (self.triangulation_accuracy_u, self.triangulation_accuracy_v);
+        }
+        let samples = |length: f32| {
+            ((self.triangulation_accuracy_u as f32 * length / longer).round() as usize)
+                .max(Self::MIN_DERIVED_ACCURACY)
+        };
+        (samples(length_u), samples(length_v))
+    }
+
+    pub fn triangulation_mode(&self) -> TriangulationMode {
+        self.triangulation_mode
+    }
+
+    pub fn adaptive_tolerance(&self) -> f32 {
+        self.adaptive_tolerance
     }
 
     pub fn alfa(&self) -> f32 {
@@ -292,12 +1201,56 @@ impl ControlsState {
     pub fn use_normal_map(&self) -> bool {
         self.use_normal_map
     }
+
+    pub fn fog(&self) -> Fog {
+        self.fog
+    }
+
+    pub fn shading_mode(&self) -> ShadingMode {
+        self.shading_mode
+    }
+
+    pub fn toon(&self) -> ToonSettings {
+        self.toon
+    }
+
+    pub fn zebra(&self) -> ZebraSettings {
+        self.zebra
+    }
+
+    pub fn debug_view(&self) -> DebugView {
+        self.debug_view
+    }
+
+    pub fn curvature_map(&self) -> CurvatureMap {
+        self.curvature_map
+    }
+
+    pub fn vector_glyphs(&self) -> VectorGlyphs {
+        self.vector_glyphs
+    }
+
+    pub fn wireframe(&self) -> Wireframe {
+        self.wireframe
+    }
+
+    pub fn stitch_patches(&self) -> bool {
+        self.stitch_patches
+    }
+
+    pub fn stitch_tolerance(&self) -> f32 {
+        self.stitch_tolerance
+    }
 }
 
 impl Default for ControlsState {
     fn default() -> Self {
         ControlsState {
-            triangulation_accuracy: 5,
+            triangulation_accuracy_u: 5,
+            triangulation_accuracy_v: 5,
+            derive_accuracy_from_net: false,
+            triangulation_mode: TriangulationMode::Uniform,
+            adaptive_tolerance: 2.0,
             alfa: 0.0,
             beta: 0.0,
             show_mesh: false,
@@ -308,6 +1261,105 @@ impl Default for ControlsState {
             show_light_source: false,
             run_animation: true,
             use_normal_map: false,
+            fog: Fog::default(),
+            shading_mode: ShadingMode::Phong,
+            toon: ToonSettings::default(),
+            zebra: ZebraSettings::default(),
+            debug_view: DebugView::Off,
+            curvature_map: CurvatureMap::default(),
+            vector_glyphs: VectorGlyphs::default(),
+            wireframe: Wireframe::default(),
+            stitch_patches: true,
+            stitch_tolerance: 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    /// Planar patch over `[x_min, x_max] x [-150, 150]`, rows follow Y and columns follow X
+    fn rectangle(x_min: f32, x_max: f32, size: usize) -> ControlPoints {
+        let coordinate =
+            |i: usize, min: f32, max: f32| min + (max - min) * i as f32 / (size - 1) as f32;
+        let points = (0..size)
+            .flat_map(|row| {
+                (0..size).map(move |col| {
+                    Vector3::new(
+                        coordinate(col, x_min, x_max),
+                        coordinate(row, 150.0, -150.0),
+                        0.0,
+                    )
+                })
+            })
+            .collect();
+        ControlPoints::new(points, size, size).unwrap()
+    }
+
+    #[test]
+    fn derived_accuracy_is_shared_along_common_boundary() {
+        let patches = [rectangle(-300.0, 0.0, 4), rectangle(0.0, 900.0, 2)];
+        let shared_edges = SharedEdge::find_all(&patches);
+        assert_eq!(shared_edges.len(), 1);
+        let controls_state = ControlsState {
+            triangulation_accuracy_u: 30,
+            triangulation_accuracy_v: 30,
+            derive_accuracy_from_net: true,
+            ..ControlsState::default()
+        };
+
+        // Each patch alone gets different count of samples along the common boundary
+        assert_eq!(controls_state.triangulation_accuracy(&patches[0]), (30, 30));
+        assert_eq!(controls_state.triangulation_accuracy(&patches[1]), (10, 30));
+        let accuracies = controls_state.triangulation_accuracies(&patches, &shared_edges);
+        assert_eq!(accuracies, vec![(30, 30), (30, 30)]);
+
+        let meshes = Mesh::triangulation(&patches, &shared_edges, &controls_state);
+        let (a, b) = (meshes[0].points(), meshes[1].points());
+        assert_eq!(a.rows(), b.rows());
+        for row in 0..a.rows() {
+            let p_a = a.at(row, a.cols() - 1).before_rotation().p();
+            let p_b = b.at(row, 0).before_rotation().p();
+            assert!((p_a - p_b).norm() < 1e-3, "row {}: {} != {}", row, p_a, p_b);
         }
     }
+
+    #[test]
+    fn derived_accuracy_of_collapsed_net_keeps_both_counts() {
+        let point = Vector3::new(1.0, 2.0, 3.0);
+        let patch = ControlPoints::new(vec![point; 4], 2, 2).unwrap();
+        let controls_state = ControlsState {
+            triangulation_accuracy_u: 12,
+            triangulation_accuracy_v: 7,
+            derive_accuracy_from_net: true,
+            ..ControlsState::default()
+        };
+        assert_eq!(controls_state.triangulation_accuracy(&patch), (12, 7));
+    }
+
+    #[test]
+    fn view_toggles_are_not_recorded_in_history() {
+        let mut app = TriangleMeshFiller::load_from_file("config/default_config.txt").unwrap();
+        app.controls_state.run_animation = !app.controls_state.run_animation;
+        app.controls_state.show_mesh = !app.controls_state.show_mesh;
+        app.controls_state.debug_view = DebugView::Curvature;
+        app.history.record(app.snapshot(), false);
+        assert_eq!(app.history.labels().count(), 1);
+
+        let kd = app.controls_state.kd;
+        app.controls_state.kd = kd / 2.0;
+        app.history.record(app.snapshot(), false);
+        assert_eq!(app.history.labels().count(), 2);
+
+        // Undo reverts the material but keeps current view state
+        let view_state = app.controls_state;
+        app.undo();
+        assert_eq!(app.controls_state.kd, kd);
+        assert_eq!(app.controls_state.run_animation, view_state.run_animation);
+        assert_eq!(app.controls_state.show_mesh, view_state.show_mesh);
+        assert_eq!(app.controls_state.debug_view, DebugView::Curvature);
+    }
 }